@@ -1,39 +1,57 @@
 #![feature(test)]
 extern crate test;
 
-use std::cmp::Ordering;
+use std::{
+    cmp::Ordering,
+    fmt,
+    io::{self, Write},
+    str::FromStr,
+};
 
 use bstr::ByteSlice;
+use itertools::Itertools;
 
 const INPUT: &[u8] = include_bytes!("../../inputs/day-01.txt");
 
-fn parse_input(input: &[u8]) -> (Vec<u32>, Vec<u32>) {
-    let (mut list1, mut list2): (Vec<u32>, Vec<u32>) = input
-        .lines()
-        .map(|line| {
-            let (num1, num2) = line.split_once_str("   ").unwrap();
-            (
-                unsafe { num1.to_str_unchecked() }.parse::<u32>().unwrap(),
-                unsafe { num2.to_str_unchecked() }.parse::<u32>().unwrap(),
-            )
-        })
-        .unzip();
-
-    list1.sort_unstable();
-    list2.sort_unstable();
+const RADIX_BITS: u32 = 11;
+const RADIX_MASK: u32 = (1 << RADIX_BITS) - 1;
 
-    (list1, list2)
+fn parse_input(input: &[u8]) -> Vec<Vec<i64>> {
+    let mut columns: Vec<Vec<i64>> = vec![];
+
+    for line in input.lines().filter(|line| !line.trim().is_empty()) {
+        let row = line
+            .fields()
+            .map(|num| unsafe { num.to_str_unchecked() }.parse::<i64>().unwrap())
+            .collect_vec();
+
+        // The first row fixes the number of columns.
+        if columns.is_empty() {
+            columns.resize(row.len(), vec![]);
+        }
+        assert_eq!(row.len(), columns.len(), "ragged row: {}", line.as_bstr());
+
+        for (column, num) in columns.iter_mut().zip(row) {
+            column.push(num);
+        }
+    }
+
+    for column in &mut columns {
+        column.sort_unstable();
+    }
+
+    columns
 }
 
-fn part_1(list1: &[u32], list2: &[u32]) -> u32 {
+fn part_1(list1: &[i64], list2: &[i64]) -> u64 {
     list1
         .iter()
         .zip(list2)
         .map(|(&a, &b)| a.abs_diff(b))
-        .sum::<u32>()
+        .sum::<u64>()
 }
 
-fn part_2(list1: &[u32], list2: &[u32]) -> u32 {
+fn part_2(list1: &[i64], list2: &[i64]) -> i64 {
     let mut iter1 = list1.iter().peekable();
     let mut iter2 = list2.iter().peekable();
 
@@ -63,10 +81,251 @@ fn part_2(list1: &[u32], list2: &[u32]) -> u32 {
     sum
 }
 
+fn parse_fixed(digits: &[u8]) -> u32 {
+    digits
+        .iter()
+        .fold(0, |acc, &c| acc * 10 + u32::from(c - b'0'))
+}
+
+// LSD radix sort, only running as many passes as the largest value needs.
+fn radix_sort(list: &mut Vec<u32>) {
+    let max = list.iter().copied().max().unwrap_or(0);
+    let mut buffer = vec![0; list.len()];
+
+    let mut shift = 0;
+    while shift < u32::BITS && max >> shift != 0 {
+        let mut offsets = [0; 1 << RADIX_BITS];
+        for &n in list.iter() {
+            offsets[((n >> shift) & RADIX_MASK) as usize] += 1;
+        }
+
+        let mut total = 0;
+        for offset in &mut offsets {
+            let count = *offset;
+            *offset = total;
+            total += count;
+        }
+
+        for &n in list.iter() {
+            let bucket = &mut offsets[((n >> shift) & RADIX_MASK) as usize];
+            buffer[*bucket] = n;
+            *bucket += 1;
+        }
+
+        std::mem::swap(list, &mut buffer);
+        shift += RADIX_BITS;
+    }
+}
+
+// Fast path for the puzzle format: two columns of equal-width unsigned numbers
+// separated by three spaces.
+fn parse_input_fixed(input: &[u8]) -> (Vec<u32>, Vec<u32>) {
+    let width = input.find_byte(b' ').unwrap();
+    let line_len = 2 * width + 3;
+
+    let capacity = input.len() / line_len;
+    let mut list1 = Vec::with_capacity(capacity);
+    let mut list2 = Vec::with_capacity(capacity);
+
+    for line in input.chunks(line_len + 1) {
+        if line.len() < line_len {
+            continue;
+        }
+        list1.push(parse_fixed(&line[..width]));
+        list2.push(parse_fixed(&line[width + 3..line_len]));
+    }
+
+    radix_sort(&mut list1);
+    radix_sort(&mut list2);
+
+    (list1, list2)
+}
+
+fn part_1_fixed(list1: &[u32], list2: &[u32]) -> u32 {
+    list1
+        .iter()
+        .zip(list2)
+        .map(|(&a, &b)| a.abs_diff(b))
+        .sum::<u32>()
+}
+
+fn part_2_fixed(list1: &[u32], list2: &[u32]) -> u32 {
+    let Some(&max) = list2.last() else {
+        return 0;
+    };
+
+    let mut counts = vec![0; max as usize + 1];
+    for &n in list2 {
+        counts[n as usize] += 1;
+    }
+
+    list1
+        .iter()
+        .filter_map(|&n| Some(n * counts.get(n as usize)?))
+        .sum()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ColumnStats {
+    len: usize,
+    min: i64,
+    max: i64,
+    sum: i64,
+    mean: f64,
+    median: f64,
+}
+
+impl fmt::Display for ColumnStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "len {}, min {}, max {}, sum {}, mean {:.2}, median {}",
+            self.len, self.min, self.max, self.sum, self.mean, self.median
+        )
+    }
+}
+
+// Expects a sorted, non-empty column as returned by `parse_input`.
+fn column_stats(column: &[i64]) -> ColumnStats {
+    let len = column.len();
+    let sum = column.iter().sum::<i64>();
+    let median = if len.is_multiple_of(2) {
+        (column[len / 2 - 1] + column[len / 2]) as f64 / 2.0
+    } else {
+        column[len / 2] as f64
+    };
+
+    ColumnStats {
+        len,
+        min: column[0],
+        max: column[len - 1],
+        sum,
+        mean: sum as f64 / len as f64,
+        median,
+    }
+}
+
+// CSV holds a single table, so each one gets its own format, while JSON holds
+// both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReportFormat {
+    // The pairings of `part_1`.
+    Csv,
+    // The similarity contributions of `part_2`.
+    SimilarityCsv,
+    Json,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "similarity-csv" => Ok(Self::SimilarityCsv),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("unknown report format: {s}")),
+        }
+    }
+}
+
+// Yields (left, right, distance) for every pair contributing to `part_1`.
+fn pairings<'a>(list1: &'a [i64], list2: &'a [i64]) -> impl Iterator<Item = (i64, i64, u64)> + 'a {
+    list1
+        .iter()
+        .zip(list2)
+        .map(|(&a, &b)| (a, b, a.abs_diff(b)))
+}
+
+// Yields (value, occurrences in right list, contribution) for every entry of
+// the left list contributing to `part_2`.
+fn contributions<'a>(
+    list1: &'a [i64],
+    list2: &'a [i64],
+) -> impl Iterator<Item = (i64, i64, i64)> + 'a {
+    let mut rest = list2;
+    list1.iter().map(move |&n| {
+        rest = &rest[rest.partition_point(|&m| m < n)..];
+        let count = rest.partition_point(|&m| m == n) as i64;
+        (n, count, n * count)
+    })
+}
+
+fn write_report(
+    out: &mut impl Write,
+    list1: &[i64],
+    list2: &[i64],
+    format: ReportFormat,
+) -> io::Result<()> {
+    match format {
+        ReportFormat::Csv => {
+            writeln!(out, "left,right,diff")?;
+            for (left, right, diff) in pairings(list1, list2) {
+                writeln!(out, "{left},{right},{diff}")?;
+            }
+        }
+        ReportFormat::SimilarityCsv => {
+            writeln!(out, "value,count,contribution")?;
+            for (value, count, contribution) in contributions(list1, list2) {
+                writeln!(out, "{value},{count},{contribution}")?;
+            }
+        }
+        ReportFormat::Json => {
+            writeln!(out, "{{")?;
+            writeln!(out, "  \"part_1\": {},", part_1(list1, list2))?;
+            writeln!(out, "  \"part_2\": {},", part_2(list1, list2))?;
+            writeln!(out, "  \"pairs\": [")?;
+            for (i, (left, right, diff)) in pairings(list1, list2).enumerate() {
+                let sep = if i == 0 { "" } else { ",\n" };
+                write!(
+                    out,
+                    "{sep}    {{\"left\": {left}, \"right\": {right}, \"diff\": {diff}}}"
+                )?;
+            }
+            writeln!(out, "\n  ],")?;
+            writeln!(out, "  \"similarity\": [")?;
+            for (i, (value, count, contribution)) in contributions(list1, list2).enumerate() {
+                let sep = if i == 0 { "" } else { ",\n" };
+                write!(
+                    out,
+                    "{sep}    {{\"value\": {value}, \"count\": {count}, \"contribution\": \
+                     {contribution}}}"
+                )?;
+            }
+            writeln!(out, "\n  ]")?;
+            writeln!(out, "}}")?;
+        }
+    }
+    Ok(())
+}
+
 fn main() {
-    let (list1, list2) = parse_input(INPUT);
-    println!("Part 1: {}", part_1(&list1, &list2));
-    println!("Part 2: {}", part_2(&list1, &list2));
+    if let Some(format) = std::env::args().find_map(|arg| {
+        arg.strip_prefix("--report=")
+            .map(|format| format.parse::<ReportFormat>().unwrap())
+    }) {
+        let columns = parse_input(INPUT);
+        write_report(&mut io::stdout().lock(), &columns[0], &columns[1], format).unwrap();
+        return;
+    }
+
+    let (list1, list2) = parse_input_fixed(INPUT);
+    println!("Part 1: {}", part_1_fixed(&list1, &list2));
+    println!("Part 2: {}", part_2_fixed(&list1, &list2));
+
+    if std::env::args().any(|arg| arg == "--stats") {
+        let columns = parse_input(INPUT);
+        for (i, column) in columns.iter().enumerate() {
+            println!("Column {i}: {}", column_stats(column));
+        }
+        for (i, j) in (0..columns.len()).tuple_combinations() {
+            println!(
+                "Columns {i}/{j}: distance {}, similarity {}",
+                part_1(&columns[i], &columns[j]),
+                part_2(&columns[i], &columns[j])
+            );
+        }
+    }
 }
 
 #[cfg(test)]
@@ -82,16 +341,125 @@ mod tests {
 3   9
 3   3";
 
+    const EXAMPLE_MULTI: &str = "3 4 -1
+4\t3   7
+2   5 3
+1   3 -4
+3   9 3
+3   3 0
+";
+
     #[test]
     fn test_part_1() {
-        let (list1, list2) = parse_input(EXAMPLE.as_bytes());
-        assert_eq!(part_1(&list1, &list2), 11);
+        let columns = parse_input(EXAMPLE.as_bytes());
+        assert_eq!(part_1(&columns[0], &columns[1]), 11);
     }
 
     #[test]
     fn test_part_2() {
-        let (list1, list2) = parse_input(EXAMPLE.as_bytes());
-        assert_eq!(part_2(&list1, &list2), 31);
+        let columns = parse_input(EXAMPLE.as_bytes());
+        assert_eq!(part_2(&columns[0], &columns[1]), 31);
+    }
+
+    #[test]
+    fn test_part_1_fixed() {
+        let (list1, list2) = parse_input_fixed(EXAMPLE.as_bytes());
+        assert_eq!(part_1_fixed(&list1, &list2), 11);
+    }
+
+    #[test]
+    fn test_part_2_fixed() {
+        let (list1, list2) = parse_input_fixed(EXAMPLE.as_bytes());
+        assert_eq!(part_2_fixed(&list1, &list2), 31);
+    }
+
+    #[test]
+    fn test_radix_sort() {
+        let mut list = (0..5000u32)
+            .map(|i| i.wrapping_mul(2_654_435_761) % 100_000)
+            .collect::<Vec<_>>();
+        let mut expected = list.clone();
+        expected.sort_unstable();
+
+        radix_sort(&mut list);
+        assert_eq!(list, expected);
+    }
+
+    #[test]
+    fn test_report() {
+        let columns = parse_input(EXAMPLE.as_bytes());
+        let (list1, list2) = (&columns[0], &columns[1]);
+
+        assert_eq!(
+            pairings(list1, list2).map(|(_, _, diff)| diff).sum::<u64>(),
+            11
+        );
+        assert_eq!(contributions(list1, list2).collect::<Vec<_>>(), [
+            (1, 0, 0),
+            (2, 0, 0),
+            (3, 3, 9),
+            (3, 3, 9),
+            (3, 3, 9),
+            (4, 1, 4)
+        ]);
+
+        let mut csv = vec![];
+        write_report(&mut csv, list1, list2, ReportFormat::Csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(
+            csv,
+            "left,right,diff\n1,3,2\n2,3,1\n3,3,0\n3,4,1\n3,5,2\n4,9,5\n"
+        );
+
+        let mut csv = vec![];
+        write_report(&mut csv, list1, list2, ReportFormat::SimilarityCsv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(
+            csv,
+            "value,count,contribution\n1,0,0\n2,0,0\n3,3,9\n3,3,9\n3,3,9\n4,1,4\n"
+        );
+        assert_eq!("similarity-csv".parse(), Ok(ReportFormat::SimilarityCsv));
+
+        let mut json = vec![];
+        write_report(&mut json, list1, list2, ReportFormat::Json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.contains("\"part_1\": 11,"));
+        assert!(json.contains("\"part_2\": 31,"));
+        assert!(json.contains("{\"left\": 4, \"right\": 9, \"diff\": 5}\n  ]"));
+        assert!(json.contains("{\"value\": 4, \"count\": 1, \"contribution\": 4}\n  ]"));
+    }
+
+    #[test]
+    #[should_panic(expected = "ragged row: 3")]
+    fn test_narrower_row() {
+        parse_input(b"1 2\n3\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "ragged row: 3 4 5")]
+    fn test_wider_row() {
+        parse_input(b"1 2\n3 4 5\n");
+    }
+
+    #[test]
+    fn test_multi_column() {
+        let columns = parse_input(EXAMPLE_MULTI.as_bytes());
+        assert_eq!(columns.len(), 3);
+        assert_eq!(columns[2], [-4, -1, 0, 3, 3, 7]);
+
+        assert_eq!(part_1(&columns[0], &columns[1]), 11);
+        assert_eq!(part_1(&columns[0], &columns[2]), 14);
+        assert_eq!(part_2(&columns[0], &columns[2]), 18);
+        assert_eq!(part_2(&columns[1], &columns[2]), 18);
+
+        assert_eq!(column_stats(&columns[2]), ColumnStats {
+            len: 6,
+            min: -4,
+            max: 7,
+            sum: 8,
+            mean: 8.0 / 6.0,
+            median: 1.5,
+        });
     }
 
     #[bench]
@@ -101,13 +469,30 @@ mod tests {
 
     #[bench]
     fn bench_part_1(b: &mut test::Bencher) {
-        let (list1, list2) = parse_input(INPUT);
-        b.iter(|| part_1(black_box(&list1), black_box(&list2)));
+        let columns = parse_input(INPUT);
+        b.iter(|| part_1(black_box(&columns[0]), black_box(&columns[1])));
     }
 
     #[bench]
     fn bench_part_2(b: &mut test::Bencher) {
-        let (list1, list2) = parse_input(INPUT);
-        b.iter(|| part_2(black_box(&list1), black_box(&list2)));
+        let columns = parse_input(INPUT);
+        b.iter(|| part_2(black_box(&columns[0]), black_box(&columns[1])));
+    }
+
+    #[bench]
+    fn bench_parse_input_fixed(b: &mut test::Bencher) {
+        b.iter(|| parse_input_fixed(black_box(INPUT)));
+    }
+
+    #[bench]
+    fn bench_part_1_fixed(b: &mut test::Bencher) {
+        let (list1, list2) = parse_input_fixed(INPUT);
+        b.iter(|| part_1_fixed(black_box(&list1), black_box(&list2)));
+    }
+
+    #[bench]
+    fn bench_part_2_fixed(b: &mut test::Bencher) {
+        let (list1, list2) = parse_input_fixed(INPUT);
+        b.iter(|| part_2_fixed(black_box(&list1), black_box(&list2)));
     }
 }
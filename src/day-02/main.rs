@@ -1,74 +1,300 @@
 #![feature(test)]
 extern crate test;
 
-use std::cmp::Ordering;
+use std::{fmt, str::FromStr};
 
 use bstr::ByteSlice;
 use itertools::Itertools;
 
 const INPUT: &[u8] = include_bytes!("../../inputs/day-02.txt");
 
-fn part_1(input: &[u8]) -> u32 {
-    input
-        .lines()
-        .filter(|line| {
-            let mut iter = line
-                .split_str(" ")
-                .map(|num| unsafe { num.to_str_unchecked() }.parse::<u32>().unwrap())
-                .tuple_windows();
-
-            let (a, b) = iter.next().unwrap();
-            let trend = a.cmp(&b);
-            if trend == Ordering::Equal || a.abs_diff(b) > 3 {
-                return false;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Trend {
+    Increasing,
+    Decreasing,
+    // Either increasing or decreasing, but the same for the whole report
+    Monotonic,
+    Any,
+}
+
+impl FromStr for Trend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "increasing" => Ok(Self::Increasing),
+            "decreasing" => Ok(Self::Decreasing),
+            "monotonic" => Ok(Self::Monotonic),
+            "any" => Ok(Self::Any),
+            _ => Err(format!("unknown trend: {s}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Tolerance {
+    max_removals: usize,
+    min_step: u32,
+    max_step: u32,
+    trend: Trend,
+}
+
+impl Tolerance {
+    const DAMPENED: Self = Self {
+        max_removals: 1,
+        ..Self::STRICT
+    };
+    const STRICT: Self = Self {
+        max_removals: 0,
+        min_step: 1,
+        max_step: 3,
+        trend: Trend::Monotonic,
+    };
+
+    // Overrides fields from `--removals=K`, `--steps=MIN-MAX` and
+    // `--trend=increasing|decreasing|monotonic|any` arguments.
+    fn with_args(mut self, args: impl Iterator<Item = String>) -> Option<Self> {
+        let mut changed = false;
+        for arg in args {
+            if let Some(removals) = arg.strip_prefix("--removals=") {
+                self.max_removals = removals.parse().unwrap();
+            } else if let Some(steps) = arg.strip_prefix("--steps=") {
+                let (min, max) = steps.split_once('-').unwrap();
+                self.min_step = min.parse().unwrap();
+                self.max_step = max.parse().unwrap();
+            } else if let Some(trend) = arg.strip_prefix("--trend=") {
+                self.trend = trend.parse().unwrap();
+            } else {
+                continue;
             }
+            changed = true;
+        }
+        changed.then_some(self)
+    }
 
-            iter.all(|(a, b)| a.cmp(&b) == trend && a.abs_diff(b) <= 3)
-        })
-        .count() as u32
+    #[inline]
+    fn step_ok(&self, a: u32, b: u32, trend: Trend) -> bool {
+        let ordered = match trend {
+            Trend::Increasing => a <= b,
+            Trend::Decreasing => a >= b,
+            Trend::Monotonic | Trend::Any => true,
+        };
+        ordered && (self.min_step..=self.max_step).contains(&a.abs_diff(b))
+    }
 }
-fn part_2(input: &[u8]) -> u32 {
-    input
-        .lines()
-        .filter(|line| {
-            let mut row = line
-                .split_str(" ")
-                .map(|num| unsafe { num.to_str_unchecked() }.parse::<u32>().unwrap())
-                .collect_vec();
-
-            let check_row = |data: &[u32], order: Ordering| {
-                let mut prev_num = data[0];
-                let mut had_mistake = false;
-
-                for &num in &data[1..] {
-                    let bad_order = num.cmp(&prev_num) != order;
-                    let bad_diff = num.abs_diff(prev_num) > 3;
-
-                    if bad_order || bad_diff {
-                        if had_mistake {
-                            return false;
-                        }
-                        had_mistake = true;
-                    } else {
-                        prev_num = num;
-                    }
-                }
-                true
+
+// Returns the indices of a minimal set of levels whose removal makes the
+// report safe, or `None` if that takes more than `max_removals` levels.
+fn levels_to_remove(levels: &[u32], tolerance: &Tolerance) -> Option<Vec<usize>> {
+    match tolerance.trend {
+        Trend::Monotonic => [Trend::Increasing, Trend::Decreasing]
+            .into_iter()
+            .filter_map(|trend| levels_to_remove_with(levels, tolerance, trend))
+            .min_by_key(Vec::len),
+        trend => levels_to_remove_with(levels, tolerance, trend),
+    }
+}
+
+fn levels_to_remove_with(
+    levels: &[u32],
+    tolerance: &Tolerance,
+    trend: Trend,
+) -> Option<Vec<usize>> {
+    let n = levels.len();
+    let k = tolerance.max_removals;
+    if n <= 1 {
+        return Some(vec![]);
+    }
+
+    // removals[i]: fewest levels removed from `levels[..=i]` while keeping
+    // `levels[i]`, together with the previously kept level.
+    let mut removals: Vec<Option<(usize, Option<usize>)>> = vec![None; n];
+    for i in 0..n {
+        if i <= k {
+            removals[i] = Some((i, None));
+        }
+
+        for j in i.saturating_sub(k + 1)..i {
+            let Some((removed, _)) = removals[j] else {
+                continue;
             };
+            let removed = removed + (i - j - 1);
+            if removed > k || !tolerance.step_ok(levels[j], levels[i], trend) {
+                continue;
+            }
+            if removals[i].is_none_or(|(best, _)| removed < best) {
+                removals[i] = Some((removed, Some(j)));
+            }
+        }
+    }
+
+    let (mut last, _) = (n.saturating_sub(k + 1)..n)
+        .filter_map(|i| Some((i, removals[i]?.0 + (n - 1 - i))))
+        .filter(|&(_, removed)| removed <= k)
+        .min_by_key(|&(_, removed)| removed)?;
+
+    let mut kept = vec![false; n];
+    loop {
+        kept[last] = true;
+        match removals[last].unwrap().1 {
+            Some(prev) => last = prev,
+            None => break,
+        }
+    }
+
+    Some((0..n).filter(|&i| !kept[i]).collect())
+}
+
+fn parse_uint(s: &[u8]) -> u32 {
+    s.iter().fold(0, |acc, &c| acc * 10 + u32::from(c - b'0'))
+}
+
+// All reports flattened into one buffer; report `i` is
+// `levels[offsets[i]..offsets[i + 1]]`.
+struct Reports {
+    levels: Vec<u32>,
+    offsets: Vec<usize>,
+}
+
+impl Reports {
+    fn iter(&self) -> impl Iterator<Item = &[u32]> {
+        self.offsets
+            .iter()
+            .tuple_windows()
+            .map(|(&start, &end)| &self.levels[start..end])
+    }
+}
+
+fn parse_input(input: &[u8]) -> Reports {
+    let mut levels = Vec::with_capacity(input.len() / 2);
+    let mut offsets = vec![0];
+
+    for line in input.lines().filter(|line| !line.is_empty()) {
+        levels.extend(line.split_str(" ").map(parse_uint));
+        offsets.push(levels.len());
+    }
+
+    Reports { levels, offsets }
+}
 
-            if check_row(&row, Ordering::Less) || check_row(&row, Ordering::Greater) {
-                return true;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UnsafeReason {
+    StepTooSmall { index: usize, step: u32 },
+    StepTooLarge { index: usize, step: u32 },
+    TrendChange { index: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Safety {
+    Safe,
+    Dampened { removed: Vec<usize> },
+    Unsafe(UnsafeReason),
+}
+
+impl fmt::Display for Safety {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Safe => write!(f, "safe"),
+            Self::Dampened { removed } => write!(f, "safe after removing levels {removed:?}"),
+            Self::Unsafe(UnsafeReason::StepTooSmall { index, step }) => {
+                write!(
+                    f,
+                    "unsafe: step of {step} between levels {index} and {} is too small",
+                    index + 1
+                )
+            }
+            Self::Unsafe(UnsafeReason::StepTooLarge { index, step }) => {
+                write!(
+                    f,
+                    "unsafe: step of {step} between levels {index} and {} is too large",
+                    index + 1
+                )
+            }
+            Self::Unsafe(UnsafeReason::TrendChange { index }) => {
+                write!(
+                    f,
+                    "unsafe: trend changes between levels {index} and {}",
+                    index + 1
+                )
+            }
+        }
+    }
+}
+
+// First pair of adjacent levels breaking the tolerance, ignoring removals.
+fn first_violation(levels: &[u32], tolerance: &Tolerance) -> Option<UnsafeReason> {
+    let mut trend = tolerance.trend;
+
+    levels
+        .iter()
+        .tuple_windows()
+        .enumerate()
+        .find_map(|(index, (&a, &b))| {
+            let step = a.abs_diff(b);
+            if step < tolerance.min_step {
+                return Some(UnsafeReason::StepTooSmall { index, step });
+            }
+            if step > tolerance.max_step {
+                return Some(UnsafeReason::StepTooLarge { index, step });
             }
-            row.reverse();
 
-            check_row(&row, Ordering::Less) || check_row(&row, Ordering::Greater)
+            let pair_trend = if a < b {
+                Trend::Increasing
+            } else {
+                Trend::Decreasing
+            };
+            match trend {
+                Trend::Monotonic if a != b => trend = pair_trend,
+                Trend::Increasing | Trend::Decreasing if a != b && trend != pair_trend => {
+                    return Some(UnsafeReason::TrendChange { index });
+                }
+                _ => (),
+            }
+            None
         })
+}
+
+fn classify(levels: &[u32], tolerance: &Tolerance) -> Safety {
+    match first_violation(levels, tolerance) {
+        None => Safety::Safe,
+        Some(reason) => match levels_to_remove(levels, tolerance) {
+            Some(removed) => Safety::Dampened { removed },
+            None => Safety::Unsafe(reason),
+        },
+    }
+}
+
+fn count_safe(reports: &Reports, tolerance: &Tolerance) -> u32 {
+    reports
+        .iter()
+        .filter(|levels| levels_to_remove(levels, tolerance).is_some())
         .count() as u32
 }
 
+fn part_1(reports: &Reports) -> u32 {
+    count_safe(reports, &Tolerance::STRICT)
+}
+
+fn part_2(reports: &Reports) -> u32 {
+    count_safe(reports, &Tolerance::DAMPENED)
+}
+
 fn main() {
-    println!("Part 1: {}", part_1(INPUT));
-    println!("Part 2: {}", part_2(INPUT));
+    let reports = parse_input(INPUT);
+    println!("Part 1: {}", part_1(&reports));
+    println!("Part 2: {}", part_2(&reports));
+
+    let tolerance = Tolerance::DAMPENED.with_args(std::env::args().skip(1));
+    if let Some(tolerance) = tolerance {
+        println!("Safe ({tolerance:?}): {}", count_safe(&reports, &tolerance));
+    }
+
+    if std::env::args().any(|arg| arg == "--classify") {
+        let tolerance = tolerance.unwrap_or(Tolerance::DAMPENED);
+        for (i, levels) in reports.iter().enumerate() {
+            println!("Report {i}: {}", classify(levels, &tolerance));
+        }
+    }
 }
 
 #[cfg(test)]
@@ -86,21 +312,95 @@ mod tests {
 
     #[test]
     fn test_part_1() {
-        assert_eq!(part_1(EXAMPLE.as_bytes()), 2);
+        let reports = parse_input(EXAMPLE.as_bytes());
+        assert_eq!(part_1(&reports), 2);
     }
 
     #[test]
     fn test_part_2() {
-        assert_eq!(part_2(EXAMPLE.as_bytes()), 4);
+        let reports = parse_input(EXAMPLE.as_bytes());
+        assert_eq!(part_2(&reports), 4);
+    }
+
+    #[test]
+    fn test_classify() {
+        let reports = parse_input(EXAMPLE.as_bytes());
+        let classes = reports
+            .iter()
+            .map(|levels| classify(levels, &Tolerance::DAMPENED))
+            .collect_vec();
+
+        assert_eq!(classes, [
+            Safety::Safe,
+            Safety::Unsafe(UnsafeReason::StepTooLarge { index: 1, step: 5 }),
+            Safety::Unsafe(UnsafeReason::StepTooLarge { index: 2, step: 4 }),
+            Safety::Dampened { removed: vec![2] },
+            Safety::Dampened { removed: vec![3] },
+            Safety::Safe,
+        ]);
+        assert_eq!(
+            classify(&[1, 3, 2, 1, 0], &Tolerance::STRICT),
+            Safety::Unsafe(UnsafeReason::TrendChange { index: 1 })
+        );
+    }
+
+    #[test]
+    fn test_levels_to_remove() {
+        let tolerance = Tolerance {
+            max_removals: 2,
+            ..Tolerance::STRICT
+        };
+        assert_eq!(
+            levels_to_remove(&[1, 3, 2, 4, 5], &Tolerance::DAMPENED),
+            Some(vec![2])
+        );
+        assert_eq!(
+            levels_to_remove(&[9, 7, 6, 2, 1], &tolerance),
+            Some(vec![3, 4])
+        );
+        assert_eq!(
+            levels_to_remove(&[1, 9, 2, 8, 3], &tolerance),
+            Some(vec![1, 3])
+        );
+        assert_eq!(
+            levels_to_remove(&[20, 1, 2, 3], &Tolerance::DAMPENED),
+            Some(vec![0])
+        );
+        assert_eq!(
+            levels_to_remove(&[1, 2, 3, 20], &Tolerance::DAMPENED),
+            Some(vec![3])
+        );
+
+        let flat = Tolerance {
+            max_removals: 0,
+            min_step: 0,
+            max_step: 5,
+            trend: Trend::Increasing,
+        };
+        assert_eq!(levels_to_remove(&[1, 1, 4, 9], &flat), Some(vec![]));
+        assert_eq!(levels_to_remove(&[9, 4, 1, 1], &flat), None);
+
+        let any = Tolerance {
+            trend: Trend::Any,
+            ..Tolerance::STRICT
+        };
+        assert_eq!(levels_to_remove(&[1, 3, 2, 4, 1], &any), Some(vec![]));
+    }
+
+    #[bench]
+    fn bench_parse_input(b: &mut test::Bencher) {
+        b.iter(|| parse_input(black_box(INPUT)));
     }
 
     #[bench]
     fn bench_part_1(b: &mut test::Bencher) {
-        b.iter(|| part_1(black_box(INPUT)));
+        let reports = parse_input(INPUT);
+        b.iter(|| part_1(black_box(&reports)));
     }
 
     #[bench]
     fn bench_part_2(b: &mut test::Bencher) {
-        b.iter(|| part_2(black_box(INPUT)));
+        let reports = parse_input(INPUT);
+        b.iter(|| part_2(black_box(&reports)));
     }
 }
@@ -1,75 +1,322 @@
 #![feature(test)]
 extern crate test;
 
+use std::{
+    fs::File,
+    io::{self, Read},
+};
+
 use bstr::ByteSlice;
+use itertools::Itertools;
 
 const INPUT: &[u8] = include_bytes!("../../inputs/day-03.txt");
 
-fn part_1(input: &[u8]) -> u32 {
-    input
-        .find_iter("mul(")
-        .map(|start| {
-            let mut first_digit = 0;
-            let mut continue_at = None;
-            for (i, &c) in input.iter().enumerate().skip(start + 4) {
-                match c {
-                    b'0'..=b'9' => {
-                        first_digit = first_digit * 10 + u32::from(c - b'0');
-                    }
-                    b',' => {
-                        continue_at = Some(i + 1);
-                        break;
-                    }
-                    _ => break,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Mul(u32, u32),
+    Do,
+    Dont,
+}
+
+// Parses a 1-3 digit operand, returning it with the number of bytes consumed.
+fn parse_operand(input: &[u8]) -> Option<(u32, usize)> {
+    let len = input
+        .iter()
+        .take(4)
+        .take_while(|c| c.is_ascii_digit())
+        .count();
+    if !(1..=3).contains(&len) {
+        return None;
+    }
+
+    let value = input[..len]
+        .iter()
+        .fold(0, |acc, &c| acc * 10 + u32::from(c - b'0'));
+    Some((value, len))
+}
+
+// Matches a single token at the start of `input`, returning it with its length.
+fn match_token(input: &[u8]) -> Option<(Token, usize)> {
+    if let Some(rest) = input.strip_prefix(b"mul(") {
+        let (a, a_len) = parse_operand(rest)?;
+        let rest = rest[a_len..].strip_prefix(b",")?;
+        let (b, b_len) = parse_operand(rest)?;
+        rest[b_len..].strip_prefix(b")")?;
+        Some((Token::Mul(a, b), 4 + a_len + 1 + b_len + 1))
+    } else if input.starts_with(b"do()") {
+        Some((Token::Do, 4))
+    } else if input.starts_with(b"don't()") {
+        Some((Token::Dont, 7))
+    } else {
+        None
+    }
+}
+
+// Yields every well-formed token in `input` together with its byte offset.
+struct Tokens<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Iterator for Tokens<'_> {
+    type Item = (usize, Token);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let start = self.pos + self.input[self.pos..].find_byteset(b"md")?;
+            match match_token(&self.input[start..]) {
+                Some((token, len)) => {
+                    self.pos = start + len;
+                    return Some((start, token));
                 }
+                None => self.pos = start + 1,
             }
+        }
+    }
+}
 
-            let Some(continue_at) = continue_at else {
-                return 0;
-            };
+fn tokenize(input: &[u8]) -> Tokens<'_> {
+    Tokens { input, pos: 0 }
+}
 
-            let mut second_digit = 0;
-            for c in &input[continue_at..] {
-                match c {
-                    b'0'..=b'9' => {
-                        second_digit = second_digit * 10 + u32::from(c - b'0');
-                    }
-                    b')' => {
-                        return first_digit * second_digit;
-                    }
-                    _ => break,
+// Longest possible token, `mul(999,999)`.
+const MAX_TOKEN_LEN: usize = 12;
+
+// Sums of all products and of the products enabled by the latest
+// `do()`/`don't()`, i.e. the results of both parts.
+struct Machine {
+    enabled: bool,
+    all: u64,
+    conditional: u64,
+}
+
+impl Machine {
+    fn new() -> Self {
+        Self {
+            enabled: true,
+            all: 0,
+            conditional: 0,
+        }
+    }
+
+    #[inline]
+    fn apply(&mut self, token: Token) {
+        match token {
+            Token::Mul(a, b) => {
+                let product = u64::from(a * b);
+                self.all += product;
+                if self.enabled {
+                    self.conditional += product;
                 }
             }
-            0
-        })
-        .sum()
+            Token::Do => self.enabled = true,
+            Token::Dont => self.enabled = false,
+        }
+    }
+}
+
+fn solve(tokens: impl Iterator<Item = (usize, Token)>) -> (u64, u64) {
+    let mut machine = Machine::new();
+    for (_, token) in tokens {
+        machine.apply(token);
+    }
+    (machine.all, machine.conditional)
+}
+
+// Incremental solver fed with arbitrary chunks. Anything that could still be
+// the start of a token cut off by the chunk boundary is carried over into the
+// next chunk.
+struct Stream {
+    machine: Machine,
+    buffer: Vec<u8>,
+}
+
+impl Stream {
+    fn new() -> Self {
+        Self {
+            machine: Machine::new(),
+            buffer: Vec::with_capacity(MAX_TOKEN_LEN),
+        }
+    }
+
+    fn feed(&mut self, chunk: &[u8]) {
+        self.buffer.extend_from_slice(chunk);
+        let safe_end = self.buffer.len().saturating_sub(MAX_TOKEN_LEN - 1);
+        self.consume(safe_end);
+    }
+
+    fn finish(mut self) -> (u64, u64) {
+        self.consume(self.buffer.len());
+        (self.machine.all, self.machine.conditional)
+    }
+
+    // Applies all tokens starting before `safe_end` and drops the bytes that
+    // can no longer be part of a token.
+    fn consume(&mut self, safe_end: usize) {
+        let mut tokens = tokenize(&self.buffer);
+        let mut consumed = 0;
+        while let Some((start, token)) = tokens.next() {
+            if start >= safe_end {
+                break;
+            }
+            self.machine.apply(token);
+            consumed = tokens.pos;
+        }
+
+        self.buffer.drain(..safe_end.max(consumed));
+    }
 }
-fn part_2(input: &[u8]) -> u32 {
-    let dont_finder = bstr::Finder::new("don't()");
-    let do_finder = bstr::Finder::new("do()");
 
-    let mut idx = 0;
-    let mut result = 0;
+fn solve_reader(mut reader: impl Read, chunk_size: usize) -> io::Result<(u64, u64)> {
+    let mut stream = Stream::new();
+    let mut chunk = vec![0; chunk_size];
+
     loop {
-        let Some(end) = dont_finder.find(&input[idx..]) else {
-            result += part_1(&input[idx..]);
-            break;
-        };
+        match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => stream.feed(&chunk[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(stream.finish())
+}
 
-        result += part_1(&input[idx..idx + end]);
+// Generic scanner state. Products are enabled while `disabled` is zero, which
+// lets toggles either reset it or nest.
+#[derive(Debug, Default)]
+struct Accumulator {
+    value: i64,
+    disabled: u32,
+}
 
-        let end_of_chunk = idx + end + dont_finder.needle().len();
-        let Some(do_start) = do_finder.find(&input[end_of_chunk..]) else {
-            break;
-        };
-        idx = end_of_chunk + do_start + do_finder.needle().len();
+impl Accumulator {
+    #[inline]
+    fn enabled(&self) -> bool {
+        self.disabled == 0
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Instruction {
+    name: &'static [u8],
+    arity: usize,
+    effect: fn(&mut Accumulator, &[u32]),
+}
+
+const MAX_ARITY: usize = 4;
+
+// Registry of instructions for the slower generic scanner; the default
+// `mul`/`do`/`don't` set is handled by `tokenize`.
+struct InstructionSet {
+    instructions: Vec<Instruction>,
+    first_bytes: Vec<u8>,
+}
+
+impl InstructionSet {
+    fn new(instructions: Vec<Instruction>) -> Self {
+        assert!(instructions.iter().all(|ins| ins.arity <= MAX_ARITY));
+        let first_bytes = instructions
+            .iter()
+            .map(|ins| ins.name[0])
+            .sorted_unstable()
+            .dedup()
+            .collect();
+
+        Self {
+            instructions,
+            first_bytes,
+        }
+    }
+
+    fn part_1() -> Self {
+        Self::new(vec![Instruction {
+            name: b"mul",
+            arity: 2,
+            effect: |acc, args| acc.value += i64::from(args[0] * args[1]),
+        }])
+    }
+
+    fn part_2() -> Self {
+        Self::new(vec![
+            Instruction {
+                name: b"mul",
+                arity: 2,
+                effect: |acc, args| {
+                    if acc.enabled() {
+                        acc.value += i64::from(args[0] * args[1]);
+                    }
+                },
+            },
+            Instruction {
+                name: b"do",
+                arity: 0,
+                effect: |acc, _| acc.disabled = 0,
+            },
+            Instruction {
+                name: b"don't",
+                arity: 0,
+                effect: |acc, _| acc.disabled = 1,
+            },
+        ])
+    }
+
+    // Matches `name(a,b,...)` with exactly `arity` 1-3 digit operands.
+    fn match_instruction(
+        ins: &Instruction,
+        input: &[u8],
+        args: &mut [u32; MAX_ARITY],
+    ) -> Option<usize> {
+        let mut rest = input.strip_prefix(ins.name)?.strip_prefix(b"(")?;
+        for (i, arg) in args.iter_mut().take(ins.arity).enumerate() {
+            if i > 0 {
+                rest = rest.strip_prefix(b",")?;
+            }
+            let (value, len) = parse_operand(rest)?;
+            *arg = value;
+            rest = &rest[len..];
+        }
+        rest = rest.strip_prefix(b")")?;
+
+        Some(input.len() - rest.len())
+    }
+
+    fn run(&self, input: &[u8]) -> i64 {
+        let mut acc = Accumulator::default();
+        let mut args = [0; MAX_ARITY];
+
+        let mut pos = 0;
+        while let Some(offset) = input[pos..].find_byteset(&self.first_bytes) {
+            let start = pos + offset;
+            pos = start + 1;
+
+            for ins in &self.instructions {
+                if let Some(len) = Self::match_instruction(ins, &input[start..], &mut args) {
+                    (ins.effect)(&mut acc, &args[..ins.arity]);
+                    pos = start + len;
+                    break;
+                }
+            }
+        }
+
+        acc.value
     }
-    result
 }
 
 fn main() {
-    println!("Part 1: {}", part_1(INPUT));
-    println!("Part 2: {}", part_2(INPUT));
+    if std::env::args().any(|arg| arg == "--generic") {
+        println!("Part 1: {}", InstructionSet::part_1().run(INPUT));
+        println!("Part 2: {}", InstructionSet::part_2().run(INPUT));
+        return;
+    }
+
+    let (part_1, part_2) = match std::env::args().nth(1) {
+        Some(path) => solve_reader(File::open(path).unwrap(), 1 << 20).unwrap(),
+        None => solve(tokenize(INPUT)),
+    };
+    println!("Part 1: {part_1}");
+    println!("Part 2: {part_2}");
 }
 
 #[cfg(test)]
@@ -85,21 +332,113 @@ mod tests {
 
     #[test]
     fn test_part_1() {
-        assert_eq!(part_1(EXAMPLE_01.as_bytes()), 161);
+        assert_eq!(solve(tokenize(EXAMPLE_01.as_bytes())).0, 161);
     }
 
     #[test]
     fn test_part_2() {
-        assert_eq!(part_2(EXAMPLE_02.as_bytes()), 48);
+        assert_eq!(solve(tokenize(EXAMPLE_02.as_bytes())).1, 48);
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(tokenize(EXAMPLE_02.as_bytes()).collect::<Vec<_>>(), [
+            (1, Token::Mul(2, 4)),
+            (20, Token::Dont),
+            (28, Token::Mul(5, 5)),
+            (48, Token::Mul(11, 8)),
+            (59, Token::Do),
+            (64, Token::Mul(8, 5)),
+        ]);
+
+        let malformed = b"mul(,5)mul(1234,5)mul(12,3456)mul(4,)mul( 1,2)do( )don't";
+        assert_eq!(tokenize(malformed).count(), 0);
+        assert_eq!(tokenize(b"mulmul(123,456)").collect::<Vec<_>>(), [(
+            3,
+            Token::Mul(123, 456)
+        )]);
+    }
+
+    #[test]
+    fn test_solve_reader() {
+        let input = format!("{EXAMPLE_02}mul(999,999)don't()mul(1,1)do()mul(12,34)");
+        let expected = solve(tokenize(input.as_bytes()));
+        assert_eq!(expected, (161 + 998_001 + 1 + 408, 48 + 998_001 + 408));
+
+        for chunk_size in 1..=input.len() {
+            assert_eq!(
+                solve_reader(input.as_bytes(), chunk_size).unwrap(),
+                expected,
+                "chunk size {chunk_size}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_instruction_set() {
+        assert_eq!(InstructionSet::part_1().run(EXAMPLE_01.as_bytes()), 161);
+        assert_eq!(InstructionSet::part_2().run(EXAMPLE_02.as_bytes()), 48);
+
+        // Toggles nest: every `off()` needs a matching `on()`.
+        let custom = InstructionSet::new(vec![
+            Instruction {
+                name: b"add",
+                arity: 2,
+                effect: |acc, args| {
+                    if acc.enabled() {
+                        acc.value += i64::from(args[0] + args[1]);
+                    }
+                },
+            },
+            Instruction {
+                name: b"sub",
+                arity: 2,
+                effect: |acc, args| {
+                    if acc.enabled() {
+                        acc.value += i64::from(args[0]) - i64::from(args[1]);
+                    }
+                },
+            },
+            Instruction {
+                name: b"off",
+                arity: 0,
+                effect: |acc, _| acc.disabled += 1,
+            },
+            Instruction {
+                name: b"on",
+                arity: 0,
+                effect: |acc, _| acc.disabled = acc.disabled.saturating_sub(1),
+            },
+        ]);
+        let program =
+            b"add(1,2)sub(1,10)off()add(5,5)off()on()sub(9,1)on()add(100,1)mul(2,2)add(1)";
+        assert_eq!(custom.run(program), 3 - 9 + 101);
+    }
+
+    #[bench]
+    fn bench_instruction_set_part_1(b: &mut test::Bencher) {
+        let instructions = InstructionSet::part_1();
+        b.iter(|| instructions.run(black_box(INPUT)));
+    }
+
+    #[bench]
+    fn bench_instruction_set_part_2(b: &mut test::Bencher) {
+        let instructions = InstructionSet::part_2();
+        b.iter(|| instructions.run(black_box(INPUT)));
+    }
+
+    #[bench]
+    fn bench_solve_reader(b: &mut test::Bencher) {
+        b.iter(|| solve_reader(black_box(INPUT), 4096).unwrap());
     }
 
     #[bench]
-    fn bench_part_1(b: &mut test::Bencher) {
-        b.iter(|| part_1(black_box(INPUT)));
+    fn bench_tokenize(b: &mut test::Bencher) {
+        b.iter(|| tokenize(black_box(INPUT)).count());
     }
 
     #[bench]
-    fn bench_part_2(b: &mut test::Bencher) {
-        b.iter(|| part_2(black_box(INPUT)));
+    fn bench_solve(b: &mut test::Bencher) {
+        b.iter(|| solve(tokenize(black_box(INPUT))));
     }
 }
@@ -2,86 +2,397 @@
 extern crate test;
 
 use bstr::ByteSlice;
-use itertools::Itertools;
 
 const INPUT: &[u8] = include_bytes!("../../inputs/day-04.txt");
 
-#[inline]
-fn count_xmas(input: &[&[u8]], x: isize, y: isize) -> u32 {
-    if input[y as usize][x as usize] != b'X' {
-        return 0;
-    }
-
-    [-1, 0, 1]
-        .into_iter()
-        .cartesian_product([-1, 0, 1])
-        .filter(|&(dx, dy)| (dx, dy) != (0, 0))
-        .map(|(delta_x, delta_y)| {
-            u32::from(
-                std::iter::zip(
-                    (1..).map(|i| x + delta_x * i),
-                    (1..).map(|i| y + delta_y * i),
-                )
-                .take(3)
-                .filter_map(|(xi, yi)| input.get(yi as usize)?.get(xi as usize))
-                .zip_longest(b"MAS".iter())
-                .all(|pair| match pair {
-                    itertools::EitherOrBoth::Both(&c, &m) => c == m,
-                    _ => false,
-                }),
-            )
+// A small pattern grid where `None` cells (written `.`) match anything.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Stencil {
+    height: usize,
+    width: usize,
+    cells: Vec<Option<u8>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Placement {
+    y: usize,
+    x: usize,
+    stencil: usize,
+}
+
+impl Stencil {
+    // Rows are separated by `/`, e.g. `M.S/.A./M.S`.
+    fn parse(pattern: &str) -> Self {
+        let rows = pattern.split('/').collect::<Vec<_>>();
+        let width = rows[0].len();
+        assert!(rows.iter().all(|row| row.len() == width));
+
+        Self {
+            height: rows.len(),
+            width,
+            cells: rows
+                .iter()
+                .flat_map(|row| row.bytes().map(|c| (c != b'.').then_some(c)))
+                .collect(),
+        }
+    }
+
+    // Rotated 90 degrees clockwise.
+    fn rotate(&self) -> Self {
+        Self {
+            height: self.width,
+            width: self.height,
+            cells: (0..self.width)
+                .flat_map(|y| {
+                    (0..self.height)
+                        .map(move |x| self.cells[(self.height - 1 - x) * self.width + y])
+                })
+                .collect(),
+        }
+    }
+
+    // Mirrored left to right.
+    fn reflect(&self) -> Self {
+        Self {
+            cells: self
+                .cells
+                .chunks_exact(self.width)
+                .flat_map(|row| row.iter().rev().copied())
+                .collect(),
+            ..*self
+        }
+    }
+
+    // All distinct rotations and reflections, starting with `self`.
+    fn variants(&self) -> Vec<Self> {
+        let mut variants: Vec<Self> = vec![];
+        for mut variant in [self.clone(), self.reflect()] {
+            for _ in 0..4 {
+                if !variants.contains(&variant) {
+                    variants.push(variant.clone());
+                }
+                variant = variant.rotate();
+            }
+        }
+        variants
+    }
+
+    #[inline]
+    fn matches_at(&self, grid: &[&[u8]], y: usize, x: usize) -> bool {
+        self.cells.iter().enumerate().all(|(i, cell)| {
+            let Some(c) = cell else {
+                return true;
+            };
+            grid.get(y + i / self.width)
+                .and_then(|row| row.get(x + i % self.width))
+                == Some(c)
         })
-        .sum::<u32>()
+    }
 }
 
-fn part_1(input: &[u8]) -> u32 {
-    let map = input.lines().collect::<Vec<_>>();
-    let rows = map.len() as isize;
-    let cols = map[0].len() as isize;
+fn find_stencils(grid: &[&[u8]], stencils: &[Stencil]) -> Vec<Placement> {
+    grid.iter()
+        .enumerate()
+        .flat_map(|(y, row)| (0..row.len()).map(move |x| (y, x)))
+        .flat_map(|(y, x)| {
+            stencils
+                .iter()
+                .enumerate()
+                .filter(move |(_, stencil)| stencil.matches_at(grid, y, x))
+                .map(move |(stencil, _)| Placement { y, x, stencil })
+        })
+        .collect()
+}
 
-    (0..rows)
-        .flat_map(|y| (0..cols).map(move |x| (x, y)))
-        .map(|(x, y)| count_xmas(&map, x, y))
-        .sum()
+// (dy, dx)
+type Direction = (isize, isize);
+
+const ALL_DIRECTIONS: [Direction; 8] = [
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+    (0, -1),
+    (0, 1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+];
+
+#[derive(Debug, Default)]
+struct TrieNode {
+    children: Vec<(u8, usize)>,
+    word: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Match {
+    y: usize,
+    x: usize,
+    direction: Direction,
+    word: usize,
 }
 
-#[inline]
-fn count_mas(input: &[&[u8]], x: isize, y: isize) -> bool {
-    if input[y as usize][x as usize] != b'A' {
-        return false;
+// Searches for any number of words at once by walking a trie of them from
+// every cell in every direction.
+struct WordSearch<'a> {
+    words: Vec<&'a [u8]>,
+    directions: Vec<Direction>,
+    nodes: Vec<TrieNode>,
+}
+
+impl<'a> WordSearch<'a> {
+    fn new(words: &[&'a [u8]], directions: &[Direction]) -> Self {
+        let mut nodes = vec![TrieNode::default()];
+        for (i, word) in words.iter().enumerate() {
+            let mut node = 0;
+            for &c in *word {
+                node = match nodes[node].children.iter().find(|&&(child, _)| child == c) {
+                    Some(&(_, next)) => next,
+                    None => {
+                        nodes.push(TrieNode::default());
+                        let next = nodes.len() - 1;
+                        nodes[node].children.push((c, next));
+                        next
+                    }
+                };
+            }
+            nodes[node].word.get_or_insert(i);
+        }
+
+        Self {
+            words: words.to_vec(),
+            directions: directions.to_vec(),
+            nodes,
+        }
+    }
+
+    fn find(&self, grid: &[&[u8]]) -> Vec<Match> {
+        let mut matches = vec![];
+
+        for (y, row) in grid.iter().enumerate() {
+            for x in 0..row.len() {
+                for &direction in &self.directions {
+                    let (mut yi, mut xi) = (y as isize, x as isize);
+                    let mut node = 0;
+
+                    while let Some(&c) = grid.get(yi as usize).and_then(|row| row.get(xi as usize))
+                    {
+                        let Some(&(_, next)) = self.nodes[node]
+                            .children
+                            .iter()
+                            .find(|&&(child, _)| child == c)
+                        else {
+                            break;
+                        };
+                        node = next;
+
+                        if let Some(word) = self.nodes[node].word {
+                            matches.push(Match {
+                                y,
+                                x,
+                                direction,
+                                word,
+                            });
+                        }
+
+                        yi += direction.0;
+                        xi += direction.1;
+                    }
+                }
+            }
+        }
+
+        matches
+    }
+
+    fn counts(&self, matches: &[Match]) -> Vec<usize> {
+        let mut counts = vec![0; self.words.len()];
+        for m in matches {
+            counts[m.word] += 1;
+        }
+        counts
+    }
+}
+
+// One bitset per letter and row, bit `x` of row `y` set where the grid has
+// that letter. Words are found for all start cells of a row at once by ANDing
+// the rows of their letters, shifted to line up with the start cell.
+struct BitGrid {
+    height: usize,
+    stride: usize,
+    index: [Option<usize>; 256],
+    masks: Vec<Vec<u64>>,
+}
+
+impl BitGrid {
+    fn new(grid: &[&[u8]], letters: &[u8]) -> Self {
+        let height = grid.len();
+        let width = grid.iter().map(|row| row.len()).max().unwrap_or(0);
+        let stride = width.div_ceil(64);
+
+        let mut index = [None; 256];
+        let mut masks = vec![];
+        for &c in letters {
+            if index[c as usize].is_none() {
+                index[c as usize] = Some(masks.len());
+                masks.push(vec![0; height * stride]);
+            }
+        }
+
+        for (y, row) in grid.iter().enumerate() {
+            for (x, &c) in row.iter().enumerate() {
+                if let Some(i) = index[c as usize] {
+                    masks[i][y * stride + x / 64] |= 1 << (x % 64);
+                }
+            }
+        }
+
+        Self {
+            height,
+            stride,
+            index,
+            masks,
+        }
+    }
+
+    #[inline]
+    fn row(&self, letter: u8, y: usize) -> &[u64] {
+        let mask = &self.masks[self.index[letter as usize].unwrap()];
+        &mask[y * self.stride..(y + 1) * self.stride]
+    }
+
+    // acc[x] &= row[x + shift], treating bits outside the row as unset.
+    #[inline]
+    fn and_shifted(acc: &mut [u64], row: &[u64], shift: isize) {
+        let word = |i: isize| {
+            usize::try_from(i)
+                .ok()
+                .and_then(|i| row.get(i))
+                .copied()
+                .unwrap_or(0)
+        };
+        let (words, bits) = (shift.div_euclid(64), shift.rem_euclid(64) as u32);
+
+        for (k, out) in acc.iter_mut().enumerate() {
+            let k = k as isize + words;
+            let lo = word(k) >> bits;
+            let hi = if bits == 0 {
+                0
+            } else {
+                word(k + 1) << (64 - bits)
+            };
+            *out &= lo | hi;
+        }
+    }
+
+    fn count_word(&self, word: &[u8], (dy, dx): Direction) -> u64 {
+        let reach = (word.len() - 1) as isize;
+        let rows = (0..self.height as isize)
+            .filter(|y| (0..self.height as isize).contains(&(y + dy * reach)));
+
+        let mut acc = vec![0; self.stride];
+        rows.map(|y| {
+            acc.fill(u64::MAX);
+            for (i, &c) in word.iter().enumerate() {
+                let i = i as isize;
+                Self::and_shifted(&mut acc, self.row(c, (y + dy * i) as usize), dx * i);
+            }
+            acc.iter().map(|w| u64::from(w.count_ones())).sum::<u64>()
+        })
+        .sum()
     }
 
-    let ul = input[y as usize - 1][x as usize - 1];
-    let ur = input[y as usize - 1][x as usize + 1];
-    let ll = input[y as usize + 1][x as usize - 1];
-    let lr = input[y as usize + 1][x as usize + 1];
+    // Counts `A`s with `MAS` on both diagonals.
+    fn count_x_mas(&self) -> u64 {
+        let mut acc = vec![0; self.stride];
+        let mut forward = vec![0; self.stride];
+        let mut backward = vec![0; self.stride];
+
+        let mut count = 0;
+        for y in 1..self.height.saturating_sub(1) {
+            acc.copy_from_slice(self.row(b'A', y));
+
+            // Top-left to bottom-right, then top-right to bottom-left.
+            for dx in [-1, 1] {
+                forward.fill(u64::MAX);
+                Self::and_shifted(&mut forward, self.row(b'M', y - 1), dx);
+                Self::and_shifted(&mut forward, self.row(b'S', y + 1), -dx);
 
-    let x1 = (ul == b'M' && lr == b'S') || (ul == b'S' && lr == b'M');
-    let x2 = (ur == b'M' && ll == b'S') || (ur == b'S' && ll == b'M');
+                backward.fill(u64::MAX);
+                Self::and_shifted(&mut backward, self.row(b'S', y - 1), dx);
+                Self::and_shifted(&mut backward, self.row(b'M', y + 1), -dx);
 
-    x1 && x2
+                for (a, (f, b)) in acc.iter_mut().zip(forward.iter().zip(&backward)) {
+                    *a &= f | b;
+                }
+            }
+
+            count += acc.iter().map(|w| u64::from(w.count_ones())).sum::<u64>();
+        }
+        count
+    }
 }
 
-fn part_2(input: &[u8]) -> u32 {
+fn part_1(input: &[u8]) -> u64 {
     let map = input.lines().collect::<Vec<_>>();
-    let rows = map.len() as isize;
-    let cols = map[0].len() as isize;
+    let grid = BitGrid::new(&map, b"XMAS");
 
-    (1..rows - 1)
-        .flat_map(|y| (1..cols - 1).map(move |x| (x, y)))
-        .filter(|&(x, y)| count_mas(&map, x, y))
-        .count() as u32
+    ALL_DIRECTIONS
+        .iter()
+        .map(|&direction| grid.count_word(b"XMAS", direction))
+        .sum()
+}
+
+fn part_2(input: &[u8]) -> u64 {
+    let map = input.lines().collect::<Vec<_>>();
+    BitGrid::new(&map, b"MAS").count_x_mas()
 }
 
 fn main() {
     println!("Part 1: {}", part_1(INPUT));
     println!("Part 2: {}", part_2(INPUT));
+
+    if let Some(words) =
+        std::env::args().find_map(|arg| arg.strip_prefix("--words=").map(String::from))
+    {
+        let words = words.split(',').map(str::as_bytes).collect::<Vec<_>>();
+        let search = WordSearch::new(&words, &ALL_DIRECTIONS);
+        let matches = search.find(&INPUT.lines().collect::<Vec<_>>());
+
+        for m in &matches {
+            println!(
+                "{} at ({}, {}) going ({}, {})",
+                search.words[m.word].as_bstr(),
+                m.y,
+                m.x,
+                m.direction.0,
+                m.direction.1
+            );
+        }
+        for (word, count) in search.words.iter().zip(search.counts(&matches)) {
+            println!("{}: {count}", word.as_bstr());
+        }
+    }
+
+    if let Some(pattern) =
+        std::env::args().find_map(|arg| arg.strip_prefix("--stencil=").map(String::from))
+    {
+        let stencils = Stencil::parse(&pattern).variants();
+        let placements = find_stencils(&INPUT.lines().collect::<Vec<_>>(), &stencils);
+
+        for Placement { y, x, stencil } in &placements {
+            println!("Variant {stencil} at ({y}, {x})");
+        }
+        println!("{pattern}: {}", placements.len());
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::hint::black_box;
 
+    use itertools::Itertools;
+
     use super::*;
 
     const EXAMPLE_01: &str = "....XXMAS.
@@ -106,6 +417,53 @@ S.S.S.S.S.
 M.M.M.M.M.
 ..........";
 
+    // The implementations the bit-parallel scanner replaced.
+    #[inline]
+    fn count_xmas(input: &[&[u8]], x: isize, y: isize) -> u32 {
+        if input[y as usize][x as usize] != b'X' {
+            return 0;
+        }
+
+        [-1, 0, 1]
+            .into_iter()
+            .cartesian_product([-1, 0, 1])
+            .filter(|&(dx, dy)| (dx, dy) != (0, 0))
+            .map(|(delta_x, delta_y)| {
+                u32::from(
+                    std::iter::zip(
+                        (1..).map(|i| x + delta_x * i),
+                        (1..).map(|i| y + delta_y * i),
+                    )
+                    .take(3)
+                    .filter_map(|(xi, yi)| input.get(yi as usize)?.get(xi as usize))
+                    .zip_longest(b"MAS".iter())
+                    .all(|pair| match pair {
+                        itertools::EitherOrBoth::Both(&c, &m) => c == m,
+                        _ => false,
+                    }),
+                )
+            })
+            .sum::<u32>()
+    }
+
+    fn part_1_naive(input: &[u8]) -> u32 {
+        let map = input.lines().collect::<Vec<_>>();
+        let rows = map.len() as isize;
+        let cols = map[0].len() as isize;
+
+        (0..rows)
+            .flat_map(|y| (0..cols).map(move |x| (x, y)))
+            .map(|(x, y)| count_xmas(&map, x, y))
+            .sum()
+    }
+
+    fn part_2_stencil(input: &[u8]) -> u32 {
+        let map = input.lines().collect::<Vec<_>>();
+        let stencils = Stencil::parse("M.S/.A./M.S").variants();
+
+        find_stencils(&map, &stencils).len() as u32
+    }
+
     #[test]
     fn test_part_1() {
         assert_eq!(part_1(EXAMPLE_01.as_bytes()), 18);
@@ -116,13 +474,122 @@ M.M.M.M.M.
         assert_eq!(part_2(EXAMPLE_02.as_bytes()), 9);
     }
 
+    #[test]
+    fn test_word_search() {
+        let grid = EXAMPLE_01.as_bytes().lines().collect::<Vec<_>>();
+        let search = WordSearch::new(&[b"XMAS", b"MAS", b"XM"], &ALL_DIRECTIONS);
+        let matches = search.find(&grid);
+
+        assert_eq!(search.counts(&matches)[0], 18);
+        assert!(matches.contains(&Match {
+            y: 0,
+            x: 5,
+            direction: (0, 1),
+            word: 0
+        }));
+        assert!(matches.contains(&Match {
+            y: 1,
+            x: 4,
+            direction: (0, -1),
+            word: 0
+        }));
+        assert!(matches
+            .iter()
+            .filter(|m| m.word == 2)
+            .all(|m| grid[m.y][m.x] == b'X'));
+
+        let search = WordSearch::new(&[b"XMAS"], &[(0, 1)]);
+        assert_eq!(search.find(&grid).len(), 3);
+    }
+
+    #[test]
+    fn test_stencil() {
+        let x_mas = Stencil::parse("M.S/.A./M.S");
+        assert_eq!(x_mas.rotate(), Stencil::parse("M.M/.A./S.S"));
+        assert_eq!(x_mas.reflect(), Stencil::parse("S.M/.A./S.M"));
+        assert_eq!(x_mas.variants().len(), 4);
+
+        let corner = Stencil::parse("AB/.C");
+        assert_eq!(corner.rotate(), Stencil::parse(".A/CB"));
+        assert_eq!(corner.variants().len(), 8);
+
+        let grid = EXAMPLE_02.as_bytes().lines().collect::<Vec<_>>();
+        let placements = find_stencils(&grid, &[x_mas]);
+        assert_eq!(placements, [
+            Placement {
+                y: 0,
+                x: 1,
+                stencil: 0
+            },
+            Placement {
+                y: 2,
+                x: 1,
+                stencil: 0
+            },
+        ]);
+
+        let line = Stencil::parse("MAS").variants();
+        assert_eq!(line.len(), 4);
+        let grid = EXAMPLE_01.as_bytes().lines().collect::<Vec<_>>();
+        assert_eq!(
+            find_stencils(&grid, &line).len(),
+            WordSearch::new(&[b"MAS"], &[(0, 1), (0, -1), (1, 0), (-1, 0)])
+                .find(&grid)
+                .len()
+        );
+    }
+
+    #[test]
+    fn test_bit_grid() {
+        // Wide enough for words to cross u64 boundaries.
+        let mut seed = 0x2545_f491_u32;
+        let input = (0..70)
+            .map(|_| {
+                (0..150)
+                    .map(|_| {
+                        seed ^= seed << 13;
+                        seed ^= seed >> 17;
+                        seed ^= seed << 5;
+                        b"XMAS"[(seed % 4) as usize] as char
+                    })
+                    .collect::<String>()
+            })
+            .join("\n");
+
+        assert_eq!(
+            part_1(input.as_bytes()),
+            u64::from(part_1_naive(input.as_bytes()))
+        );
+        assert_eq!(
+            part_2(input.as_bytes()),
+            u64::from(part_2_stencil(input.as_bytes()))
+        );
+    }
+
+    #[bench]
+    fn bench_word_search(b: &mut test::Bencher) {
+        let grid = INPUT.lines().collect::<Vec<_>>();
+        let search = WordSearch::new(&[b"XMAS"], &ALL_DIRECTIONS);
+        b.iter(|| search.find(black_box(&grid)).len());
+    }
+
     #[bench]
     fn bench_part_1(b: &mut test::Bencher) {
         b.iter(|| part_1(black_box(INPUT)));
     }
 
+    #[bench]
+    fn bench_part_1_naive(b: &mut test::Bencher) {
+        b.iter(|| part_1_naive(black_box(INPUT)));
+    }
+
     #[bench]
     fn bench_part_2(b: &mut test::Bencher) {
         b.iter(|| part_2(black_box(INPUT)));
     }
+
+    #[bench]
+    fn bench_part_2_stencil(b: &mut test::Bencher) {
+        b.iter(|| part_2_stencil(black_box(INPUT)));
+    }
 }
@@ -1,143 +1,413 @@
 #![feature(test)]
 extern crate test;
 
+use std::fmt;
+
 use bstr::ByteSlice;
+use fnv::FnvHashMap;
 use itertools::Itertools;
 
 const INPUT: &[u8] = include_bytes!("../../inputs/day-05.txt");
 
-fn part_1(input: &[u8]) -> u32 {
-    let (rules, instructions) = input.split_once_str("\n\n").unwrap();
+fn parse_uint(s: &[u8]) -> u32 {
+    s.iter().fold(0, |acc, &c| acc * 10 + u32::from(c - b'0'))
+}
 
-    let rules = rules.lines().fold([[false; 100]; 100], |mut acc, line| {
-        let (before, after) = line.split_once_str("|").unwrap();
-        let (before, after) = (
-            unsafe { before.to_str_unchecked() }
-                .parse::<usize>()
-                .unwrap(),
-            unsafe { after.to_str_unchecked() }
-                .parse::<usize>()
-                .unwrap(),
-        );
-        acc[before][after] = true;
-        acc
-    });
+// Ordering rules between arbitrary page IDs. Pages are interned to dense
+// indices, and each page stores the set of pages that must come after it as a
+// bitset over those indices.
+#[derive(Debug, Default)]
+struct RuleSet {
+    index: FnvHashMap<u32, usize>,
+    after: Vec<Vec<u64>>,
+}
 
-    instructions
-        .lines()
-        .filter_map(|line| {
-            let mut acc: Vec<usize> = vec![];
-
-            let ins = line
-                .split_str(",")
-                .map(|n| unsafe { n.to_str_unchecked() }.parse::<usize>().unwrap())
-                .collect_vec();
-
-            ins.iter()
-                .all(|&n| {
-                    let cond = acc.iter().all(|&m| rules[m][n]);
-                    acc.push(n);
-                    cond
-                })
-                .then_some(ins[ins.len() / 2] as u32)
+impl RuleSet {
+    fn intern(&mut self, page: u32) -> usize {
+        *self.index.entry(page).or_insert_with(|| {
+            self.after.push(vec![]);
+            self.after.len() - 1
         })
-        .sum()
+    }
+
+    // Adds the rule `before|after`.
+    fn insert(&mut self, before: u32, after: u32) {
+        let before = self.intern(before);
+        let after = self.intern(after);
+
+        let bits = &mut self.after[before];
+        if bits.len() <= after / 64 {
+            bits.resize(after / 64 + 1, 0);
+        }
+        bits[after / 64] |= 1 << (after % 64);
+    }
+
+    #[inline]
+    fn precedes_interned(&self, before: usize, after: usize) -> bool {
+        self.after[before]
+            .get(after / 64)
+            .is_some_and(|bits| bits & (1 << (after % 64)) != 0)
+    }
+
+    fn interned(&self, update: &[u32]) -> Vec<Option<usize>> {
+        update
+            .iter()
+            .map(|page| self.index.get(page).copied())
+            .collect()
+    }
+
+    // An update is valid if no rule requires a later page to come first.
+    fn is_valid(&self, update: &[u32]) -> bool {
+        let interned = self.interned(update);
+
+        interned.iter().enumerate().all(|(i, &later)| {
+            interned[..i].iter().all(|&earlier| match (earlier, later) {
+                (Some(earlier), Some(later)) => !self.precedes_interned(later, earlier),
+                _ => true,
+            })
+        })
+    }
+
+    // Orders `update` by the rules between its own pages only, ignoring every
+    // other page.
+    fn corrected_order(&self, update: &[u32]) -> Result<Vec<u32>, Cycle> {
+        #[derive(Clone, Copy, PartialEq, Eq)]
+        enum Mark {
+            New,
+            Active,
+            Done,
+        }
+
+        struct Search<'a> {
+            rules: &'a RuleSet,
+            update: &'a [u32],
+            interned: Vec<Option<usize>>,
+            marks: Vec<Mark>,
+            path: Vec<usize>,
+            stack: Vec<u32>,
+        }
+
+        impl Search<'_> {
+            fn dfs(&mut self, node: usize) -> Result<(), Cycle> {
+                self.marks[node] = Mark::Active;
+                self.path.push(node);
+
+                for next in 0..self.update.len() {
+                    let (Some(a), Some(b)) = (self.interned[node], self.interned[next]) else {
+                        continue;
+                    };
+                    if !self.rules.precedes_interned(a, b) {
+                        continue;
+                    }
+                    match self.marks[next] {
+                        Mark::New => self.dfs(next)?,
+                        Mark::Active => {
+                            let start = self.path.iter().position(|&n| n == next).unwrap();
+                            let chain = self.path[start..].iter().chain([&next]);
+                            return Err(Cycle(chain.map(|&n| self.update[n]).collect()));
+                        }
+                        Mark::Done => (),
+                    }
+                }
+
+                self.path.pop();
+                self.marks[node] = Mark::Done;
+                self.stack.push(self.update[node]);
+                Ok(())
+            }
+        }
+
+        let mut search = Search {
+            rules: self,
+            update,
+            interned: self.interned(update),
+            marks: vec![Mark::New; update.len()],
+            path: vec![],
+            stack: Vec::with_capacity(update.len()),
+        };
+
+        for node in 0..update.len() {
+            if search.marks[node] == Mark::New {
+                search.dfs(node)?;
+            }
+        }
+
+        search.stack.reverse();
+        Ok(search.stack)
+    }
 }
 
-fn toposort(items: &[usize], rules: &[[bool; 100]; 100]) -> Vec<usize> {
-    let mut visited = [false; 100];
-    let mut stack = Vec::with_capacity(items.len());
-    let mut result = vec![];
-
-    fn dfs(
-        node: usize,
-        visited: &mut [bool; 100],
-        stack: &mut Vec<usize>,
-        rules: &[[bool; 100]; 100],
-    ) {
-        visited[node] = true;
-
-        for (i, &rule) in (0..100).zip(rules[node].iter()) {
-            if rule && !visited[i] {
-                dfs(i, visited, stack, rules);
+// A chain of rules `a|b`, `b|c`, ..., `z|a`, stored as `[a, b, c, ..., z, a]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Cycle(Vec<u32>);
+
+impl fmt::Display for Cycle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rule cycle: ")?;
+        for (i, (before, after)) in self.0.iter().tuple_windows().enumerate() {
+            if i > 0 {
+                write!(f, " -> ")?;
             }
+            write!(f, "{before}|{after}")?;
         }
+        Ok(())
+    }
+}
+
+// Rule `before|after` broken by `before` appearing after `after`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Violation {
+    before: u32,
+    after: u32,
+    before_pos: usize,
+    after_pos: usize,
+}
+
+// Takes `page` out of the update at index `from` and reinserts it at `to`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Move {
+    page: u32,
+    from: usize,
+    to: usize,
+}
 
-        stack.push(node);
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Diagnosis {
+    violations: Vec<Violation>,
+    moves: Vec<Move>,
+    corrected: Vec<u32>,
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for v in &self.violations {
+            writeln!(
+                f,
+                "violates {}|{}: {} at {} comes after {} at {}",
+                v.before, v.after, v.before, v.before_pos, v.after, v.after_pos
+            )?;
+        }
+        for m in &self.moves {
+            writeln!(f, "move {} from {} to {}", m.page, m.from, m.to)?;
+        }
+        write!(f, "corrected: {}", self.corrected.iter().join(","))
     }
+}
+
+impl RuleSet {
+    fn violations(&self, update: &[u32]) -> Vec<Violation> {
+        let interned = self.interned(update);
 
-    for &item in items {
-        if !visited[item] {
-            dfs(item, &mut visited, &mut stack, rules);
+        (0..update.len())
+            .tuple_combinations()
+            .filter(|&(i, j)| match (interned[i], interned[j]) {
+                (Some(earlier), Some(later)) => self.precedes_interned(later, earlier),
+                _ => false,
+            })
+            .map(|(i, j)| Violation {
+                before: update[j],
+                after: update[i],
+                before_pos: j,
+                after_pos: i,
+            })
+            .collect()
+    }
+
+    // Like `corrected_order`, but always places the earliest page of the
+    // update that has no pending predecessors next, keeping it as close to the
+    // original order as the rules allow. Pages marked in `kept` also wait for
+    // the kept pages before them, so they stay in their original order.
+    fn stable_order(&self, update: &[u32], kept: &[bool]) -> Result<Vec<u32>, Cycle> {
+        let interned = self.interned(update);
+        let mut placed = vec![false; update.len()];
+        let mut order = Vec::with_capacity(update.len());
+
+        while order.len() < update.len() {
+            let next = (0..update.len()).find(|&i| {
+                !placed[i]
+                    && (0..update.len()).all(|j| {
+                        placed[j]
+                            || !(matches!(
+                                (interned[j], interned[i]),
+                                (Some(a), Some(b)) if self.precedes_interned(a, b)
+                            ) || (kept[i] && kept[j] && j < i))
+                    })
+            });
+            let Some(next) = next else {
+                return Err(self.corrected_order(update).unwrap_err());
+            };
+
+            placed[next] = true;
+            order.push(update[next]);
         }
+
+        Ok(order)
     }
 
-    while let Some(item) = stack.pop() {
-        result.push(item);
+    // The largest set of pages that can stay where they are: none of them may
+    // have to come before an earlier one, following the rules between the
+    // update's own pages transitively.
+    fn kept_pages(&self, update: &[u32]) -> Vec<bool> {
+        let n = update.len();
+        let interned = self.interned(update);
+        let mut reach = (0..n)
+            .map(|i| {
+                (0..n)
+                    .map(|j| {
+                        matches!(
+                            (interned[i], interned[j]),
+                            (Some(a), Some(b)) if self.precedes_interned(a, b)
+                        )
+                    })
+                    .collect_vec()
+            })
+            .collect_vec();
+        for k in 0..n {
+            let through = reach[k].clone();
+            for row in reach.iter_mut().filter(|row| row[k]) {
+                for (r, &t) in row.iter_mut().zip(&through) {
+                    *r |= t;
+                }
+            }
+        }
+
+        // Page `j` having to come before an earlier page `i` orders the two,
+        // and these pairs form a partial order. The pages to keep are its
+        // largest antichain, found from a maximum matching by König's theorem.
+        let inverted = (0..n)
+            .map(|j| (0..j).filter(|&i| reach[j][i]).collect_vec())
+            .collect_vec();
+
+        fn augment(
+            j: usize,
+            inverted: &[Vec<usize>],
+            matched: &mut [Option<usize>],
+            seen: &mut [bool],
+        ) -> bool {
+            for &i in &inverted[j] {
+                if !seen[i] {
+                    seen[i] = true;
+                    if matched[i].is_none_or(|k| augment(k, inverted, matched, seen)) {
+                        matched[i] = Some(j);
+                        return true;
+                    }
+                }
+            }
+            false
+        }
+
+        let mut matched = vec![None; n];
+        for j in 0..n {
+            augment(j, &inverted, &mut matched, &mut vec![false; n]);
+        }
+
+        // Alternating paths from the unmatched left side.
+        let mut left = vec![true; n];
+        for &j in matched.iter().flatten() {
+            left[j] = false;
+        }
+        let mut right = vec![false; n];
+        let mut queue = (0..n).filter(|&j| left[j]).collect_vec();
+        while let Some(j) = queue.pop() {
+            for &i in &inverted[j] {
+                if !right[i] {
+                    right[i] = true;
+                    if let Some(k) = matched[i].filter(|&k| !left[k]) {
+                        left[k] = true;
+                        queue.push(k);
+                    }
+                }
+            }
+        }
+
+        (0..n).map(|i| left[i] && !right[i]).collect()
     }
 
-    result
+    // The fewest moves that fix `update`: the pages of `kept_pages` stay,
+    // every other page is moved once.
+    fn diagnose(&self, update: &[u32]) -> Result<Diagnosis, Cycle> {
+        let kept = self.kept_pages(update);
+        let corrected = self.stable_order(update, &kept)?;
+
+        // Moving each page right behind its corrected predecessor, in corrected
+        // order, never separates pages that were already placed.
+        let mut current = update.to_vec();
+        let mut moves = vec![];
+        for (t, &page) in corrected.iter().enumerate() {
+            if kept[update.iter().position(|&p| p == page).unwrap()] {
+                continue;
+            }
+            let from = current.iter().position(|&p| p == page).unwrap();
+            current.remove(from);
+            let to = match t {
+                0 => 0,
+                _ => current.iter().position(|&p| p == corrected[t - 1]).unwrap() + 1,
+            };
+            current.insert(to, page);
+            moves.push(Move { page, from, to });
+        }
+        debug_assert_eq!(current, corrected);
+
+        Ok(Diagnosis {
+            violations: self.violations(update),
+            moves,
+            corrected,
+        })
+    }
 }
 
-fn part_2(input: &[u8]) -> u32 {
-    let (rules, instructions) = input.split_once_str("\n\n").unwrap();
+fn parse_input(input: &[u8]) -> (RuleSet, Vec<Vec<u32>>) {
+    let (rules, updates) = input.split_once_str("\n\n").unwrap();
 
-    let rules = rules.lines().fold([[false; 100]; 100], |mut acc, line| {
+    let mut rule_set = RuleSet::default();
+    for line in rules.lines() {
         let (before, after) = line.split_once_str("|").unwrap();
-        let (before, after) = (
-            unsafe { before.to_str_unchecked() }
-                .parse::<usize>()
-                .unwrap(),
-            unsafe { after.to_str_unchecked() }
-                .parse::<usize>()
-                .unwrap(),
-        );
-        acc[before][after] = true;
-        acc
-    });
-
-    let sorted = {
-        let items: [usize; 100] = std::array::from_fn(|i| i);
-        toposort(&items, &rules)
-    };
-
-    let sorted_pos = sorted
-        .into_iter()
-        .enumerate()
-        .fold([0; 100], |mut acc, (i, n)| {
-            acc[n] = i;
-            acc
-        });
-
-    instructions
+        rule_set.insert(parse_uint(before), parse_uint(after));
+    }
+
+    let updates = updates
         .lines()
-        .filter_map(|line| {
-            let mut acc: Vec<usize> = vec![];
-
-            let mut ins = line
-                .split_str(",")
-                .map(|n| unsafe { n.to_str_unchecked() }.parse::<usize>().unwrap())
-                .collect_vec();
-
-            ins.iter()
-                .any(|&n| {
-                    let cond = acc.iter().all(|&m| rules[m][n]);
-                    acc.push(n);
-                    !cond
-                })
-                .then(|| {
-                    let target_idx = ins.len() / 2;
-                    let (_, result, _) =
-                        ins.select_nth_unstable_by_key(target_idx / 2, |&n| sorted_pos[n]);
-                    *result as u32
-                })
-        })
+        .filter(|line| !line.is_empty())
+        .map(|line| line.split_str(",").map(parse_uint).collect_vec())
+        .collect_vec();
+
+    (rule_set, updates)
+}
+
+fn part_1(rules: &RuleSet, updates: &[Vec<u32>]) -> u32 {
+    updates
+        .iter()
+        .filter(|update| rules.is_valid(update))
+        .map(|update| update[update.len() / 2])
         .sum()
 }
 
+fn part_2(rules: &RuleSet, updates: &[Vec<u32>]) -> Result<u32, Cycle> {
+    let mut result = 0;
+    for update in updates.iter().filter(|update| !rules.is_valid(update)) {
+        let sorted = rules.corrected_order(update)?;
+        result += sorted[sorted.len() / 2];
+    }
+    Ok(result)
+}
+
 fn main() {
-    println!("Part 1: {}", part_1(INPUT));
-    println!("Part 2: {}", part_2(INPUT));
+    let (rules, updates) = parse_input(INPUT);
+    println!("Part 1: {}", part_1(&rules, &updates));
+    match part_2(&rules, &updates) {
+        Ok(result) => println!("Part 2: {result}"),
+        Err(cycle) => println!("Part 2: {cycle}"),
+    }
+
+    if std::env::args().any(|arg| arg == "--diagnose") {
+        for update in updates.iter().filter(|update| !rules.is_valid(update)) {
+            println!("\nUpdate {}:", update.iter().join(","));
+            match rules.diagnose(update) {
+                Ok(diagnosis) => println!("{diagnosis}"),
+                Err(cycle) => println!("{cycle}"),
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -177,21 +447,115 @@ mod tests {
 
     #[test]
     fn test_part_1() {
-        assert_eq!(part_1(EXAMPLE.as_bytes()), 143);
+        let (rules, updates) = parse_input(EXAMPLE.as_bytes());
+        assert_eq!(part_1(&rules, &updates), 143);
     }
 
     #[test]
     fn test_part_2() {
-        assert_eq!(part_2(EXAMPLE.as_bytes()), 123);
+        let (rules, updates) = parse_input(EXAMPLE.as_bytes());
+        assert_eq!(part_2(&rules, &updates), Ok(123));
+    }
+
+    #[test]
+    fn test_toposort_cycles() {
+        // Cyclic as a whole, but not within the updates.
+        let input = "1|2\n2|3\n3|1\n\n2,1\n3,2\n1,3";
+        let (rules, updates) = parse_input(input.as_bytes());
+        assert_eq!(part_2(&rules, &updates), Ok(2 + 3 + 1));
+
+        let input = "1|2\n2|3\n3|1\n4|1\n\n4,2,1,3";
+        let (rules, updates) = parse_input(input.as_bytes());
+        let cycle = part_2(&rules, &updates).unwrap_err();
+        assert_eq!(cycle, Cycle(vec![1, 2, 3, 1]));
+        assert_eq!(cycle.to_string(), "rule cycle: 1|2 -> 2|3 -> 3|1");
+    }
+
+    #[test]
+    fn test_rule_set() {
+        let mut rules = RuleSet::default();
+        for page in 0..1000 {
+            rules.insert(page * 7 + 100_000, page * 7 + 100_007);
+        }
+
+        assert!(rules.is_valid(&[100_000, 100_007, 5, 100_014]));
+        assert!(!rules.is_valid(&[100_014, 100_000, 100_007]));
+        assert!(rules.is_valid(&[106_993, 100_000]));
+
+        rules.insert(106_993, 100_000);
+        assert!(!rules.is_valid(&[100_000, 106_993]));
+
+        let sorted = rules
+            .corrected_order(&[107_000, 5, 106_986, 106_993])
+            .unwrap();
+        assert_eq!(sorted.len(), 4);
+        assert!(rules.is_valid(&sorted));
+    }
+
+    #[test]
+    fn test_diagnose() {
+        let (rules, updates) = parse_input(EXAMPLE.as_bytes());
+
+        let diagnosis = rules.diagnose(&updates[3]).unwrap();
+        assert_eq!(diagnosis.violations, [Violation {
+            before: 97,
+            after: 75,
+            before_pos: 1,
+            after_pos: 0
+        }]);
+        assert_eq!(diagnosis.moves, [Move {
+            page: 97,
+            from: 1,
+            to: 0
+        }]);
+        assert_eq!(diagnosis.corrected, [97, 75, 47, 61, 53]);
+
+        let diagnosis = rules.diagnose(&updates[5]).unwrap();
+        assert_eq!(diagnosis.violations.len(), 4);
+        assert_eq!(diagnosis.corrected, [97, 75, 47, 29, 13]);
+        assert_eq!(
+            diagnosis.to_string(),
+            "violates 75|13: 75 at 2 comes after 13 at 1
+violates 29|13: 29 at 3 comes after 13 at 1
+violates 47|13: 47 at 4 comes after 13 at 1
+violates 47|29: 47 at 4 comes after 29 at 3
+move 47 from 4 to 3
+move 13 from 1 to 4
+corrected: 97,75,47,29,13"
+        );
+
+        let diagnosis = rules.diagnose(&updates[0]).unwrap();
+        assert!(diagnosis.violations.is_empty() && diagnosis.moves.is_empty());
+
+        // Keeping the longest run of some corrected order in place isn't
+        // enough: here 3, 2, 5 and 4 can all stay.
+        let input = "0|4\n0|5\n1|2\n1|3\n1|4\n2|4\n3|4\n\n3,2,5,0,4,1";
+        let (rules, updates) = parse_input(input.as_bytes());
+        let diagnosis = rules.diagnose(&updates[0]).unwrap();
+        assert_eq!(diagnosis.moves.len(), 2);
+        assert!(rules.is_valid(&diagnosis.corrected));
+        let mut moved = updates[0].clone();
+        for m in &diagnosis.moves {
+            moved.remove(m.from);
+            moved.insert(m.to, m.page);
+        }
+        assert_eq!(moved, diagnosis.corrected);
+    }
+
+    #[bench]
+    fn bench_parse_input(b: &mut test::Bencher) {
+        b.iter(|| parse_input(black_box(INPUT)));
     }
 
     #[bench]
     fn bench_part_1(b: &mut test::Bencher) {
-        b.iter(|| part_1(black_box(INPUT)));
+        let (rules, updates) = parse_input(INPUT);
+        b.iter(|| part_1(black_box(&rules), black_box(&updates)));
     }
 
     #[bench]
     fn bench_part_2(b: &mut test::Bencher) {
-        b.iter(|| part_2(black_box(INPUT)));
+        let (rules, updates) = parse_input(INPUT);
+        b.iter(|| part_2(black_box(&rules), black_box(&updates)).unwrap());
     }
 }
@@ -2,7 +2,6 @@
 extern crate test;
 
 use bstr::ByteSlice;
-use fnv::FnvHashSet;
 use itertools::Itertools;
 use rayon::prelude::*;
 
@@ -17,6 +16,10 @@ enum Direction {
 }
 
 impl Direction {
+    fn symbol(self) -> u8 {
+        b"^>v<"[self as usize]
+    }
+
     fn turn_right(&self) -> Self {
         match self {
             Self::North => Self::East,
@@ -33,8 +36,8 @@ enum Tile {
     Scaffold,
 }
 
-fn parse_input(input: &[u8]) -> ((i32, i32, Direction), Vec<Vec<Tile>>) {
-    let mut robot = (0, 0, Direction::North);
+fn parse_input(input: &[u8]) -> (Vec<Robot>, Vec<Vec<Tile>>) {
+    let mut robots = vec![];
 
     let map = input
         .lines()
@@ -42,69 +45,79 @@ fn parse_input(input: &[u8]) -> ((i32, i32, Direction), Vec<Vec<Tile>>) {
         .map(|(y, line)| {
             line.iter()
                 .enumerate()
-                .map(|(x, &c)| match c {
-                    b'#' => Tile::Scaffold,
-                    b'.' => Tile::Empty,
-                    b'^' => {
-                        robot = (x as i32, y as i32, Direction::North);
-                        Tile::Empty
-                    }
-                    _ => unreachable!(),
+                .map(|(x, &c)| {
+                    let dir = match c {
+                        b'#' => return Tile::Scaffold,
+                        b'.' => return Tile::Empty,
+                        b'^' => Direction::North,
+                        b'>' => Direction::East,
+                        b'v' => Direction::South,
+                        b'<' => Direction::West,
+                        _ => unreachable!(),
+                    };
+                    robots.push((x as i32, y as i32, dir));
+                    Tile::Empty
                 })
                 .collect_vec()
         })
         .collect_vec();
 
-    (robot, map)
+    (robots, map)
 }
 
-fn get_part_1_path(
-    mut robot: (i32, i32, Direction),
-    map: &[Vec<Tile>],
-) -> Vec<(i32, i32, Direction)> {
-    let mut visisted = FnvHashSet::default();
-    visisted.insert((robot.0, robot.1));
-
-    let mut path = vec![];
+type Robot = (i32, i32, Direction);
 
-    loop {
-        let (x, y, dir) = robot;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Event {
+    // Stepped forward onto a new cell.
+    Moved(Robot),
+    // Turned right in place in front of an obstacle.
+    Turned(Robot),
+    // Walked off the map from the given state.
+    Exited(Robot),
+    // Turned into a state already seen, so the guard walks in circles.
+    Looped(Robot),
+}
 
-        let (next_x, next_y) = match dir {
-            Direction::North => (x, y - 1),
-            Direction::East => (x + 1, y),
-            Direction::South => (x, y + 1),
-            Direction::West => (x - 1, y),
-        };
+// Walks the guard one step at a time, yielding what happened after each step.
+// Ends after `Exited` or `Looped`.
+struct Simulation<'a> {
+    map: &'a [Vec<Tile>],
+    robot: Robot,
+    // An extra obstacle on top of `map`, as (x, y).
+    obstacle: Option<(i32, i32)>,
+    turns: VisitedSet,
+    done: bool,
+}
 
-        if next_x < 0 || next_y < 0 {
-            return path;
+impl<'a> Simulation<'a> {
+    fn new(robot: Robot, map: &'a [Vec<Tile>]) -> Self {
+        Self {
+            map,
+            robot,
+            obstacle: None,
+            turns: VisitedSet::new(map.len() * map[0].len() * 4),
+            done: false,
         }
+    }
 
-        match map
-            .get(next_y as usize)
-            .and_then(|row| row.get(next_x as usize))
-        {
-            Some(Tile::Empty) => {
-                visisted.insert((next_x, next_y));
-                path.push((next_x, next_y, dir));
-                robot = (next_x, next_y, dir);
-            }
-            Some(Tile::Scaffold) => {
-                robot = (x, y, dir.turn_right());
-            }
-            None => return path,
+    fn with_obstacle(robot: Robot, map: &'a [Vec<Tile>], (x, y): (usize, usize)) -> Self {
+        Self {
+            obstacle: Some((x as i32, y as i32)),
+            ..Self::new(robot, map)
         }
     }
 }
 
-fn part_1(mut robot: (i32, i32, Direction), map: &[Vec<Tile>]) -> u32 {
-    let mut visisted = FnvHashSet::default();
-    visisted.insert((robot.0, robot.1));
+impl Iterator for Simulation<'_> {
+    type Item = Event;
 
-    loop {
-        let (x, y, dir) = robot;
+    fn next(&mut self) -> Option<Event> {
+        if self.done {
+            return None;
+        }
 
+        let (x, y, dir) = self.robot;
         let (next_x, next_y) = match dir {
             Direction::North => (x, y - 1),
             Direction::East => (x + 1, y),
@@ -112,85 +125,452 @@ fn part_1(mut robot: (i32, i32, Direction), map: &[Vec<Tile>]) -> u32 {
             Direction::West => (x - 1, y),
         };
 
-        if next_x < 0 || next_y < 0 {
-            return visisted.len() as u32;
-        }
+        let tile = if self.obstacle == Some((next_x, next_y)) {
+            Some(&Tile::Scaffold)
+        } else {
+            usize::try_from(next_y)
+                .ok()
+                .zip(usize::try_from(next_x).ok())
+                .and_then(|(next_y, next_x)| self.map.get(next_y)?.get(next_x))
+        };
 
-        match map
-            .get(next_y as usize)
-            .and_then(|row| row.get(next_x as usize))
-        {
+        match tile {
             Some(Tile::Empty) => {
-                visisted.insert((next_x, next_y));
-                robot = (next_x, next_y, dir);
+                self.robot = (next_x, next_y, dir);
+                Some(Event::Moved(self.robot))
             }
             Some(Tile::Scaffold) => {
-                robot = (x, y, dir.turn_right());
+                self.robot = (x, y, dir.turn_right());
+                let width = self.map[0].len();
+                let state = (y as usize * width + x as usize) * 4 + self.robot.2 as usize;
+                if self.turns.insert(state) {
+                    Some(Event::Turned(self.robot))
+                } else {
+                    self.done = true;
+                    Some(Event::Looped(self.robot))
+                }
+            }
+            None => {
+                self.done = true;
+                Some(Event::Exited(self.robot))
             }
-            None => return visisted.len() as u32,
         }
     }
 }
 
-fn robot_is_loop(mut robot: (i32, i32, Direction), map: &[Vec<Tile>]) -> bool {
-    let mut visisted = FnvHashSet::default();
-    visisted.insert(robot);
+fn get_part_1_path(robot: Robot, map: &[Vec<Tile>]) -> Vec<Robot> {
+    Simulation::new(robot, map)
+        .filter_map(|event| match event {
+            Event::Moved(robot) => Some(robot),
+            _ => None,
+        })
+        .collect()
+}
 
-    loop {
-        let (x, y, dir) = robot;
+fn part_1(robot: Robot, map: &[Vec<Tile>]) -> u32 {
+    let width = map[0].len();
+    let mut visited = VisitedSet::new(width * map.len());
+    visited.insert(robot.1 as usize * width + robot.0 as usize);
 
-        let (next_x, next_y) = match dir {
-            Direction::North => (x, y - 1),
-            Direction::East => (x + 1, y),
-            Direction::South => (x, y + 1),
-            Direction::West => (x - 1, y),
-        };
+    1 + Simulation::new(robot, map)
+        .filter(|event| match *event {
+            Event::Moved((x, y, _)) => visited.insert(y as usize * width + x as usize),
+            _ => false,
+        })
+        .count() as u32
+}
+
+// Marker in `JumpTable` for walking off the map.
+const EXIT: u32 = u32::MAX;
+
+// Dense bitset over (cell, direction) states that remembers which words it
+// touched, so clearing it between runs is cheap.
+struct VisitedSet {
+    bits: Vec<u64>,
+    touched: Vec<usize>,
+}
 
-        if next_x < 0 || next_y < 0 {
+impl VisitedSet {
+    fn new(len: usize) -> Self {
+        Self {
+            bits: vec![0; len.div_ceil(64)],
+            touched: vec![],
+        }
+    }
+
+    // Returns `false` if `state` was already in the set.
+    #[inline]
+    fn insert(&mut self, state: usize) -> bool {
+        let word = &mut self.bits[state / 64];
+        let mask = 1 << (state % 64);
+        if *word & mask != 0 {
             return false;
         }
+        if *word == 0 {
+            self.touched.push(state / 64);
+        }
+        *word |= mask;
+        true
+    }
 
-        match map
-            .get(next_y as usize)
-            .and_then(|row| row.get(next_x as usize))
-        {
-            Some(Tile::Empty) => {
-                robot = (next_x, next_y, dir);
+    fn clear(&mut self) {
+        for word in self.touched.drain(..) {
+            self.bits[word] = 0;
+        }
+    }
+}
+
+// For every cell and direction, the cell the guard stops at in front of the
+// next obstacle, or `EXIT`.
+struct JumpTable {
+    width: usize,
+    height: usize,
+    jumps: Vec<[u32; 4]>,
+}
+
+impl JumpTable {
+    fn new(map: &[Vec<Tile>]) -> Self {
+        let height = map.len();
+        let width = map[0].len();
+        let idx = |x: usize, y: usize| (y * width + x) as u32;
+        let mut jumps = vec![[EXIT; 4]; width * height];
+
+        for x in 0..width {
+            let mut stop = EXIT;
+            for y in 0..height {
+                match map[y][x] {
+                    Tile::Scaffold => stop = idx(x, y + 1),
+                    Tile::Empty => jumps[idx(x, y) as usize][Direction::North as usize] = stop,
+                }
             }
-            Some(Tile::Scaffold) => {
-                robot = (x, y, dir.turn_right());
-                if !visisted.insert(robot) {
-                    return true;
+
+            let mut stop = EXIT;
+            for y in (0..height).rev() {
+                match map[y][x] {
+                    Tile::Scaffold if y > 0 => stop = idx(x, y - 1),
+                    Tile::Scaffold => (),
+                    Tile::Empty => jumps[idx(x, y) as usize][Direction::South as usize] = stop,
                 }
             }
-            None => return false,
+        }
+
+        for y in 0..height {
+            let mut stop = EXIT;
+            for x in 0..width {
+                match map[y][x] {
+                    Tile::Scaffold => stop = idx(x + 1, y),
+                    Tile::Empty => jumps[idx(x, y) as usize][Direction::West as usize] = stop,
+                }
+            }
+
+            let mut stop = EXIT;
+            for x in (0..width).rev() {
+                match map[y][x] {
+                    Tile::Scaffold if x > 0 => stop = idx(x - 1, y),
+                    Tile::Scaffold => (),
+                    Tile::Empty => jumps[idx(x, y) as usize][Direction::East as usize] = stop,
+                }
+            }
+        }
+
+        Self {
+            width,
+            height,
+            jumps,
         }
     }
-}
 
-fn part_2(robot: (i32, i32, Direction), map: &[Vec<Tile>]) -> u32 {
-    get_part_1_path(robot, map)
-        .into_par_iter()
-        .filter(|&(x, y, d)| {
-            let mut map_copy = map.to_vec();
-            map_copy[y as usize][x as usize] = Tile::Scaffold;
+    // Whether the guard loops once an extra obstacle is placed on `obstacle`.
+    fn is_loop(
+        &self,
+        pos: usize,
+        dir: Direction,
+        obstacle: usize,
+        visited: &mut VisitedSet,
+    ) -> bool {
+        self.walk(pos, dir, obstacle, visited, |_, _| ())
+    }
+
+    // Jumps from obstacle to obstacle, calling `on_turn` with the state after
+    // every new turn, and returns whether the guard loops. Instead of patching
+    // the table, every jump checks if the extra obstacle cuts it short.
+    fn walk(
+        &self,
+        mut pos: usize,
+        mut dir: Direction,
+        obstacle: usize,
+        visited: &mut VisitedSet,
+        mut on_turn: impl FnMut(usize, Direction),
+    ) -> bool {
+        let (ox, oy) = (obstacle % self.width, obstacle / self.width);
+
+        loop {
+            let target = self.jumps[pos][dir as usize];
+            let (x, y) = (pos % self.width, pos / self.width);
+            let (tx, ty) = if target == EXIT {
+                match dir {
+                    Direction::North => (x, 0),
+                    Direction::East => (self.width - 1, y),
+                    Direction::South => (x, self.height - 1),
+                    Direction::West => (0, y),
+                }
+            } else {
+                (target as usize % self.width, target as usize / self.width)
+            };
 
-            let r = match d {
-                Direction::North => (x, y + 1, d.turn_right()),
-                Direction::East => (x - 1, y, d.turn_right()),
-                Direction::South => (x, y - 1, d.turn_right()),
-                Direction::West => (x + 1, y, d.turn_right()),
+            let blocked = match dir {
+                Direction::North => ox == x && oy < y && oy >= ty,
+                Direction::East => oy == y && ox > x && ox <= tx,
+                Direction::South => ox == x && oy > y && oy <= ty,
+                Direction::West => oy == y && ox < x && ox >= tx,
             };
 
-            robot_is_loop(r, &map_copy)
+            pos = if blocked {
+                match dir {
+                    Direction::North => obstacle + self.width,
+                    Direction::East => obstacle - 1,
+                    Direction::South => obstacle - self.width,
+                    Direction::West => obstacle + 1,
+                }
+            } else if target == EXIT {
+                return false;
+            } else {
+                target as usize
+            };
+            dir = dir.turn_right();
+
+            if !visited.insert(pos * 4 + dir as usize) {
+                return true;
+            }
+            on_turn(pos, dir);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LoopObstruction {
+    // Where the extra obstacle goes, as (x, y).
+    position: (usize, usize),
+    // The guard's state after every turn, from bumping into the new obstacle
+    // until the loop closes.
+    path: Vec<Robot>,
+}
+
+fn part_2(robot: Robot, map: &[Vec<Tile>]) -> Vec<LoopObstruction> {
+    let table = JumpTable::new(map);
+    let width = table.width;
+    let start = robot.1 as usize * width + robot.0 as usize;
+
+    // Only the first time the guard enters a cell matters: placing the
+    // obstacle there, the guard walks the same path until just before it.
+    let mut seen = VisitedSet::new(width * table.height);
+    seen.insert(start);
+    let candidates = get_part_1_path(robot, map)
+        .into_iter()
+        .filter(|&(x, y, _)| seen.insert(y as usize * width + x as usize))
+        .collect_vec();
+
+    candidates
+        .into_par_iter()
+        .map_init(
+            || VisitedSet::new(width * table.height * 4),
+            |visited, (x, y, d)| {
+                let obstacle = y as usize * width + x as usize;
+                let before = match d {
+                    Direction::North => obstacle + width,
+                    Direction::East => obstacle - 1,
+                    Direction::South => obstacle - width,
+                    Direction::West => obstacle + 1,
+                };
+
+                visited.clear();
+                let mut path = vec![];
+                table
+                    .walk(before, d, obstacle, visited, |pos, dir| {
+                        path.push(((pos % width) as i32, (pos / width) as i32, dir));
+                    })
+                    .then_some(LoopObstruction {
+                        position: (x as usize, y as usize),
+                        path,
+                    })
+            },
+        )
+        .flatten()
+        .collect()
+}
+
+fn render_tiles(map: &[Vec<Tile>]) -> Vec<Vec<u8>> {
+    map.iter()
+        .map(|row| {
+            row.iter()
+                .map(|tile| match tile {
+                    Tile::Empty => b'.',
+                    Tile::Scaffold => b'#',
+                })
+                .collect()
         })
-        .count() as u32
+        .collect()
+}
+
+fn join_rows(grid: Vec<Vec<u8>>) -> String {
+    grid.into_iter()
+        .map(|row| String::from_utf8(row).unwrap())
+        .join("\n")
+}
+
+// The map with every cell the guard visits marked `X`, the candidate
+// obstructions `O` and the start as the guard's arrow.
+fn render_obstructions(robot: Robot, map: &[Vec<Tile>], obstructions: &[(usize, usize)]) -> String {
+    let mut grid = render_tiles(map);
+    for (x, y, _) in get_part_1_path(robot, map) {
+        grid[y as usize][x as usize] = b'X';
+    }
+    for &(x, y) in obstructions {
+        grid[y][x] = b'O';
+    }
+    grid[robot.1 as usize][robot.0 as usize] = robot.2.symbol();
+    join_rows(grid)
+}
+
+// The guard's walk with an extra obstacle on `obstruction`, drawn like the
+// puzzle: `|` and `-` for straight moves, `+` for turns and crossings.
+fn render_path(robot: Robot, map: &[Vec<Tile>], obstruction: (usize, usize)) -> String {
+    let mut grid = render_tiles(map);
+    for event in Simulation::with_obstacle(robot, map, obstruction) {
+        let ((x, y, _), mark) = match event {
+            Event::Moved(robot @ (_, _, Direction::North | Direction::South)) => (robot, b'|'),
+            Event::Moved(robot) => (robot, b'-'),
+            Event::Turned(robot) | Event::Looped(robot) => (robot, b'+'),
+            Event::Exited(_) => break,
+        };
+        let cell = &mut grid[y as usize][x as usize];
+        *cell = match *cell {
+            b'.' => mark,
+            old if old == mark => mark,
+            _ => b'+',
+        };
+    }
+    grid[obstruction.1][obstruction.0] = b'O';
+    grid[robot.1 as usize][robot.0 as usize] = robot.2.symbol();
+    join_rows(grid)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct GuardReport {
+    robot: Robot,
+    // Visited cells as (x, y), in the order they were first entered.
+    visited: Vec<(usize, usize)>,
+    loops: bool,
+}
+
+fn guard_report(robot: Robot, map: &[Vec<Tile>]) -> GuardReport {
+    let width = map[0].len();
+    let mut seen = VisitedSet::new(width * map.len());
+    let mut visited = vec![];
+    let mut loops = false;
+
+    let start = std::iter::once(Event::Moved(robot));
+    for event in start.chain(Simulation::new(robot, map)) {
+        match event {
+            Event::Moved((x, y, _)) if seen.insert(y as usize * width + x as usize) => {
+                visited.push((x as usize, y as usize));
+            }
+            Event::Looped(_) => loops = true,
+            _ => (),
+        }
+    }
+
+    GuardReport {
+        robot,
+        visited,
+        loops,
+    }
+}
+
+// Cells where a single extra obstacle makes every guard walk in circles.
+// Guards do not block each other.
+fn trapping_obstructions(robots: &[Robot], map: &[Vec<Tile>]) -> Vec<(usize, usize)> {
+    let table = JumpTable::new(map);
+    let (width, height) = (table.width, table.height);
+    let reports = robots
+        .iter()
+        .map(|&robot| guard_report(robot, map))
+        .collect_vec();
+
+    // An obstacle off the path of a guard leaves it unaffected, so guards that
+    // leave the map limit the candidates to the cells they all pass.
+    let mut candidates = map
+        .iter()
+        .flatten()
+        .map(|&tile| tile == Tile::Empty)
+        .collect_vec();
+    for &(x, y, _) in robots {
+        candidates[y as usize * width + x as usize] = false;
+    }
+    for report in reports.iter().filter(|report| !report.loops) {
+        let mut on_path = vec![false; width * height];
+        for &(x, y) in &report.visited {
+            on_path[y * width + x] = true;
+        }
+        for (candidate, on_path) in candidates.iter_mut().zip(on_path) {
+            *candidate &= on_path;
+        }
+    }
+
+    (0..width * height)
+        .filter(|&i| candidates[i])
+        .collect_vec()
+        .into_par_iter()
+        .map_init(
+            || VisitedSet::new(width * height * 4),
+            |visited, obstacle| {
+                robots
+                    .iter()
+                    .all(|&(x, y, dir)| {
+                        visited.clear();
+                        table.is_loop(y as usize * width + x as usize, dir, obstacle, visited)
+                    })
+                    .then_some((obstacle % width, obstacle / width))
+            },
+        )
+        .flatten()
+        .collect()
 }
 
 fn main() {
-    let (robot, map) = parse_input(INPUT);
-    println!("Part 1: {}", part_1(robot, &map));
-    println!("Part 2: {}", part_2(robot, &map));
+    let (robots, map) = parse_input(INPUT);
+    println!("Part 1: {}", part_1(robots[0], &map));
+    let loops = part_2(robots[0], &map);
+    println!("Part 2: {}", loops.len());
+
+    if std::env::args().any(|arg| arg == "--obstructions") {
+        let positions = loops.iter().map(|l| l.position).collect_vec();
+        println!("{}\n", render_obstructions(robots[0], &map, &positions));
+        for l in &loops {
+            println!(
+                "Obstruction at {:?}: loop with {} turns\n{}\n",
+                l.position,
+                l.path.len(),
+                render_path(robots[0], &map, l.position)
+            );
+        }
+    }
+
+    if std::env::args().any(|arg| arg == "--guards") {
+        for (i, &robot) in robots.iter().enumerate() {
+            let report = guard_report(robot, &map);
+            println!(
+                "Guard {i} at ({}, {}) facing {:?}: {} cells visited, {}",
+                robot.0,
+                robot.1,
+                robot.2,
+                report.visited.len(),
+                if report.loops { "loops" } else { "exits" }
+            );
+        }
+        let obstructions = trapping_obstructions(&robots, &map);
+        println!("Obstructions trapping every guard: {obstructions:?}");
+    }
 }
 
 #[cfg(test)]
@@ -212,15 +592,200 @@ mod tests {
 
     #[test]
     fn test_part_1() {
-        let (robot, map) = parse_input(EXAMPLE.as_bytes());
+        let (robots, map) = parse_input(EXAMPLE.as_bytes());
+        let robot = robots[0];
         assert_eq!(part_1(robot, &map), 41);
     }
 
     #[test]
     fn test_part_2() {
-        let (robot, map) = parse_input(EXAMPLE.as_bytes());
+        let (robots, map) = parse_input(EXAMPLE.as_bytes());
+        let robot = robots[0];
+
+        let loops = part_2(robot, &map);
+        let positions = loops.iter().map(|l| l.position).sorted().collect_vec();
+        assert_eq!(positions, [(1, 8), (3, 6), (3, 8), (6, 7), (7, 7), (7, 9)]);
+
+        // The loop path starts by bumping into the new obstacle.
+        let l = loops.iter().find(|l| l.position == (3, 6)).unwrap();
+        assert_eq!(l.path, [
+            (4, 6, Direction::North),
+            (4, 1, Direction::East),
+            (8, 1, Direction::South),
+            (8, 6, Direction::West)
+        ]);
+    }
+
+    #[test]
+    fn test_render() {
+        let (robots, map) = parse_input(EXAMPLE.as_bytes());
+        let robot = robots[0];
+
+        assert_eq!(
+            render_path(robot, &map, (3, 6)),
+            "....#.....
+....+---+#
+....|...|.
+..#.|...|.
+....|..#|.
+....|...|.
+.#.O^---+.
+........#.
+#.........
+......#..."
+        );
+        assert_eq!(
+            render_path(robot, &map, (6, 7)),
+            "....#.....
+....+---+#
+....|...|.
+..#.|...|.
+..+-+-+#|.
+..|.|.|.|.
+.#+-^-+-+.
+......O.#.
+#.........
+......#..."
+        );
+
+        let positions = part_2(robot, &map).iter().map(|l| l.position).collect_vec();
+        assert_eq!(
+            render_obstructions(robot, &map, &positions),
+            "....#.....
+....XXXXX#
+....X...X.
+..#.X...X.
+..XXXXX#X.
+..X.X.X.X.
+.#XO^XXXX.
+.XXXXXOO#.
+#OXOXXXX..
+......#O.."
+        );
+    }
+
+    #[test]
+    fn test_simulation() {
+        let (robots, map) = parse_input(EXAMPLE.as_bytes());
+        let robot = robots[0];
+        let events = Simulation::new(robot, &map).collect_vec();
+
+        assert_eq!(events[0], Event::Moved((4, 5, Direction::North)));
+        assert_eq!(events[5], Event::Turned((4, 1, Direction::East)));
+        assert_eq!(
+            events
+                .iter()
+                .filter(|e| matches!(e, Event::Turned(_)))
+                .count(),
+            10
+        );
+        assert_eq!(
+            events.last(),
+            Some(&Event::Exited((7, 9, Direction::South)))
+        );
+
+        // One of the part 2 example positions traps the guard in a loop.
+        let mut map_copy = map.to_vec();
+        map_copy[6][3] = Tile::Scaffold;
+        assert!(matches!(
+            Simulation::new(robot, &map_copy).last(),
+            Some(Event::Looped(_))
+        ));
+    }
+
+    #[test]
+    fn test_jump_table() {
+        let (robots, map) = parse_input(EXAMPLE.as_bytes());
+        let robot = robots[0];
+        let width = map[0].len();
+        let start = robot.1 as usize * width + robot.0 as usize;
+
+        // Rebuilding the table with the obstacle must agree with the overlay.
+        let mut visited = VisitedSet::new(width * map.len() * 4);
+        let mut loops = 0;
+        for obstacle in 0..width * map.len() {
+            let (x, y) = (obstacle % width, obstacle / width);
+            if obstacle == start || map[y][x] == Tile::Scaffold {
+                continue;
+            }
+
+            let mut map_copy = map.to_vec();
+            map_copy[y][x] = Tile::Scaffold;
+            let rebuilt = JumpTable::new(&map_copy);
+            visited.clear();
+            let expected = rebuilt.is_loop(start, robot.2, obstacle, &mut visited);
+
+            visited.clear();
+            let overlay = JumpTable::new(&map).is_loop(start, robot.2, obstacle, &mut visited);
+            assert_eq!(overlay, expected, "obstacle at ({x}, {y})");
+            loops += u32::from(expected);
+        }
+        assert_eq!(loops, 6);
+    }
+
+    #[test]
+    fn test_multiple_guards() {
+        let (robots, map) = parse_input(EXAMPLE.as_bytes());
+        assert_eq!(trapping_obstructions(&robots, &map), [
+            (3, 6),
+            (6, 7),
+            (7, 7),
+            (1, 8),
+            (3, 8),
+            (7, 9)
+        ]);
+
+        // A second guard that leaves the map limits the candidates to its path.
+        let input = EXAMPLE.replace("........#.", "...>....#.");
+        let (robots, map) = parse_input(input.as_bytes());
+        assert_eq!(robots, [(4, 6, Direction::North), (3, 7, Direction::East)]);
+
+        let report = guard_report(robots[1], &map);
+        assert_eq!(report.visited, [
+            (3, 7),
+            (4, 7),
+            (5, 7),
+            (6, 7),
+            (7, 7),
+            (7, 8),
+            (7, 9)
+        ]);
+        assert!(!report.loops);
+
+        let obstructions = trapping_obstructions(&robots, &map);
+        assert_eq!(obstructions, [(7, 7), (7, 9)]);
+        for &(x, y) in &obstructions {
+            let mut map_copy = map.to_vec();
+            map_copy[y][x] = Tile::Scaffold;
+            assert!(robots
+                .iter()
+                .all(|&robot| guard_report(robot, &map_copy).loops));
+        }
 
-        assert_eq!(part_2(robot, &map), 6);
+        // With a wall on (3, 6) the first guard loops without any obstruction.
+        let input = input.replace(".#..^.....", ".#.#^.....");
+        let (robots, map) = parse_input(input.as_bytes());
+        assert!(guard_report(robots[0], &map).loops);
+        assert_eq!(trapping_obstructions(&robots, &map), [(7, 7), (7, 9)]);
+
+        // On its own, any obstruction that keeps it looping counts, on its path
+        // or not.
+        let expected = (0..map.len())
+            .cartesian_product(0..map[0].len())
+            .map(|(y, x)| (x, y))
+            .filter(|&(x, y)| {
+                map[y][x] == Tile::Empty && (robots[0].0, robots[0].1) != (x as i32, y as i32)
+            })
+            .filter(|&(x, y)| {
+                let mut map_copy = map.to_vec();
+                map_copy[y][x] = Tile::Scaffold;
+                guard_report(robots[0], &map_copy).loops
+            })
+            .collect_vec();
+        let obstructions = trapping_obstructions(&robots[..1], &map);
+        assert_eq!(obstructions, expected);
+        assert_eq!(obstructions.len(), 73);
+        assert!(obstructions.contains(&(0, 0)));
     }
 
     #[bench]
@@ -230,12 +795,14 @@ mod tests {
 
     #[bench]
     fn bench_part_1(b: &mut test::Bencher) {
-        let (robot, map) = parse_input(INPUT);
+        let (robots, map) = parse_input(INPUT);
+        let robot = robots[0];
         b.iter(|| part_1(black_box(robot), black_box(&map)));
     }
     #[bench]
     fn bench_part_2(b: &mut test::Bencher) {
-        let (robot, map) = parse_input(INPUT);
+        let (robots, map) = parse_input(INPUT);
+        let robot = robots[0];
 
         b.iter(|| part_2(black_box(robot), black_box(&map)));
     }
@@ -2,95 +2,449 @@
 extern crate test;
 
 use bstr::ByteSlice;
+use fnv::FnvHashMap;
 use itertools::Itertools;
 use rayon::prelude::*;
 
 const INPUT: &[u8] = include_bytes!("../../inputs/day-07.txt");
 
-enum Operation {
-    Add,
-    Mul,
-    Concat,
+// A binary operator that can be placed between two numbers of an equation.
+trait Operator: Sync {
+    // How the operator is written in configs and expressions.
+    fn symbol(&self) -> &'static str;
+
+    // `None` if the result is undefined or overflows.
+    fn apply(&self, a: u64, b: u64) -> Option<u64>;
+
+    // Whether `apply(a, b) >= a` for every `b > 0`, so the solver can drop
+    // partial results that already exceed the target.
+    fn is_monotonic(&self) -> bool {
+        false
+    }
+
+    // Whether `unapply` is implemented, so the solver can search backwards
+    // from the target.
+    fn is_invertible(&self) -> bool {
+        false
+    }
+
+    // The only `a` with `apply(a, b) == result` for `b > 0`, or `None` if
+    // there is none. Operators with several such `a` are not invertible.
+    fn unapply(&self, _result: u64, _b: u64) -> Option<u64> {
+        None
+    }
+}
+
+struct Add;
+struct Sub;
+struct Mul;
+struct Div;
+struct Pow;
+struct Xor;
+struct Concat;
+
+impl Operator for Add {
+    fn symbol(&self) -> &'static str {
+        "+"
+    }
+
+    #[inline]
+    fn apply(&self, a: u64, b: u64) -> Option<u64> {
+        a.checked_add(b)
+    }
+
+    fn is_monotonic(&self) -> bool {
+        true
+    }
+
+    fn is_invertible(&self) -> bool {
+        true
+    }
+
+    #[inline]
+    fn unapply(&self, result: u64, b: u64) -> Option<u64> {
+        result.checked_sub(b)
+    }
+}
+
+impl Operator for Sub {
+    fn symbol(&self) -> &'static str {
+        "-"
+    }
+
+    #[inline]
+    fn apply(&self, a: u64, b: u64) -> Option<u64> {
+        a.checked_sub(b)
+    }
+
+    fn is_invertible(&self) -> bool {
+        true
+    }
+
+    #[inline]
+    fn unapply(&self, result: u64, b: u64) -> Option<u64> {
+        result.checked_add(b)
+    }
 }
 
-impl Operation {
+impl Operator for Mul {
+    fn symbol(&self) -> &'static str {
+        "*"
+    }
+
     #[inline]
-    fn execute(&self, a: u64, b: u64) -> u64 {
-        match self {
-            Self::Add => a + b,
-            Self::Mul => a * b,
-            Self::Concat => {
-                let a_shift = if b >= 10 {
-                    if b >= 100 {
-                        if b >= 1_000 {
-                            10_000
-                        } else {
-                            1_000
-                        }
-                    } else {
-                        100
-                    }
-                } else {
-                    10
-                };
-
-                a * a_shift + b
+    fn apply(&self, a: u64, b: u64) -> Option<u64> {
+        a.checked_mul(b)
+    }
+
+    fn is_monotonic(&self) -> bool {
+        true
+    }
+
+    fn is_invertible(&self) -> bool {
+        true
+    }
+
+    #[inline]
+    fn unapply(&self, result: u64, b: u64) -> Option<u64> {
+        result.is_multiple_of(b).then_some(result / b)
+    }
+}
+
+impl Operator for Div {
+    fn symbol(&self) -> &'static str {
+        "/"
+    }
+
+    // Integer division, rounding down.
+    #[inline]
+    fn apply(&self, a: u64, b: u64) -> Option<u64> {
+        a.checked_div(b)
+    }
+}
+
+impl Operator for Pow {
+    fn symbol(&self) -> &'static str {
+        "**"
+    }
+
+    #[inline]
+    fn apply(&self, a: u64, b: u64) -> Option<u64> {
+        a.checked_pow(b.try_into().ok()?)
+    }
+
+    fn is_invertible(&self) -> bool {
+        true
+    }
+
+    #[inline]
+    fn unapply(&self, result: u64, b: u64) -> Option<u64> {
+        let b = u32::try_from(b).ok().filter(|&b| b > 0)?;
+        if b == 1 {
+            return Some(result);
+        }
+
+        // Binary search for the integer `b`-th root. Roots of a `u64` past the
+        // square root of `u64::MAX` overflow.
+        let (mut lo, mut hi) = (0, result.min(u32::MAX as u64) + 1);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            match mid.checked_pow(b) {
+                Some(pow) if pow < result => lo = mid + 1,
+                _ => hi = mid,
             }
         }
+        (lo.checked_pow(b) == Some(result)).then_some(lo)
     }
 }
 
+impl Operator for Xor {
+    fn symbol(&self) -> &'static str {
+        "^"
+    }
+
+    #[inline]
+    fn apply(&self, a: u64, b: u64) -> Option<u64> {
+        Some(a ^ b)
+    }
+
+    fn is_invertible(&self) -> bool {
+        true
+    }
+
+    #[inline]
+    fn unapply(&self, result: u64, b: u64) -> Option<u64> {
+        Some(result ^ b)
+    }
+}
+
+impl Operator for Concat {
+    fn symbol(&self) -> &'static str {
+        "||"
+    }
+
+    #[inline]
+    fn apply(&self, a: u64, b: u64) -> Option<u64> {
+        let digits = b.checked_ilog10().unwrap_or(0) + 1;
+        a.checked_mul(10u64.checked_pow(digits)?)?.checked_add(b)
+    }
+
+    fn is_monotonic(&self) -> bool {
+        true
+    }
+
+    fn is_invertible(&self) -> bool {
+        true
+    }
+
+    #[inline]
+    fn unapply(&self, result: u64, b: u64) -> Option<u64> {
+        let shift = 10u64.checked_pow(b.checked_ilog10().unwrap_or(0) + 1)?;
+        (result % shift == b).then_some(result / shift)
+    }
+}
+
+const OPERATORS: &[&dyn Operator] = &[&Add, &Sub, &Mul, &Div, &Pow, &Xor, &Concat];
+
+// Parses a comma-separated list of operator symbols, such as `+,*,||`.
+fn parse_operators(config: &str) -> Result<Vec<&'static dyn Operator>, String> {
+    config
+        .split(',')
+        .map(|symbol| {
+            OPERATORS
+                .iter()
+                .find(|op| op.symbol() == symbol)
+                .copied()
+                .ok_or_else(|| format!("unknown operator: {symbol}"))
+        })
+        .collect()
+}
+
 fn parse_uint(s: &[u8]) -> u64 {
     s.iter().fold(0, |acc, &c| acc * 10 + (c - b'0') as u64)
 }
 
 fn part_1(input: &[u8]) -> u64 {
-    solve(input, &[Operation::Add, Operation::Mul])
+    solve(input, &[&Add, &Mul])
 }
 
 fn part_2(input: &[u8]) -> u64 {
-    solve(input, &[Operation::Add, Operation::Mul, Operation::Concat])
+    solve(input, &[&Add, &Mul, &Concat])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Search {
+    // Applies operators left to right, starting from the first number.
+    Forward,
+    // Undoes operators right to left, starting from the target.
+    Backward,
 }
 
-fn solve(input: &[u8], ops: &[Operation]) -> u64 {
+fn parse_equation(line: &[u8]) -> (u64, Vec<u64>) {
+    let (prefix, suffix) = line.split_once_str(": ").unwrap();
+    (
+        parse_uint(prefix),
+        suffix.split_str(" ").map(parse_uint).collect_vec(),
+    )
+}
+
+// Searches backwards when every operator can be undone.
+fn solve(input: &[u8], ops: &[&dyn Operator]) -> u64 {
+    let search = if ops.iter().all(|op| op.is_invertible()) {
+        Search::Backward
+    } else {
+        Search::Forward
+    };
+    solve_with(input, ops, search)
+}
+
+fn solve_with(input: &[u8], ops: &[&dyn Operator], search: Search) -> u64 {
     input
         .lines()
         .collect_vec()
         .into_par_iter()
         .filter_map(|line| {
-            let (target, numbers) = {
-                let (prefix, suffix) = line.split_once_str(": ").unwrap();
-                (
-                    parse_uint(prefix),
-                    suffix.split_str(" ").map(parse_uint).collect_vec(),
-                )
+            let (target, numbers) = parse_equation(line);
+            let solvable = match search {
+                Search::Backward if !numbers.contains(&0) => {
+                    is_solvable_backward(target, &numbers, ops)
+                }
+                _ => is_solvable_forward(target, &numbers, ops),
             };
+            solvable.then_some(target)
+        })
+        .sum()
+}
 
-            let mut stack = vec![(1, numbers[0])];
+fn is_solvable_forward(target: u64, numbers: &[u64], ops: &[&dyn Operator]) -> bool {
+    let prune = ops.iter().all(|op| op.is_monotonic()) && !numbers.contains(&0);
+    let mut stack = vec![(1, numbers[0])];
 
-            while let Some((idx, result)) = stack.pop() {
-                let is_last = idx + 1 == numbers.len();
+    while let Some((idx, result)) = stack.pop() {
+        let is_last = idx + 1 == numbers.len();
 
-                for op in ops {
-                    let next = op.execute(result, numbers[idx]);
-                    if is_last && next == target {
-                        return Some(target);
-                    }
+        for op in ops {
+            let Some(next) = op.apply(result, numbers[idx]) else {
+                continue;
+            };
+            if is_last && next == target {
+                return true;
+            }
 
-                    if !is_last && next < target {
-                        stack.push((idx + 1, next));
-                    }
-                }
+            if !is_last && (!prune || next <= target) {
+                stack.push((idx + 1, next));
             }
+        }
+    }
 
-            None
-        })
-        .sum()
+    false
+}
+
+// Every operator must be invertible and every number positive. Branches die
+// as soon as an operator can't be undone, like a `*` that doesn't divide.
+fn is_solvable_backward(target: u64, numbers: &[u64], ops: &[&dyn Operator]) -> bool {
+    let mut stack = vec![(numbers.len() - 1, target)];
+
+    while let Some((idx, result)) = stack.pop() {
+        if idx == 0 {
+            if result == numbers[0] {
+                return true;
+            }
+            continue;
+        }
+
+        for op in ops {
+            if let Some(prev) = op.unapply(result, numbers[idx]) {
+                stack.push((idx - 1, prev));
+            }
+        }
+    }
+
+    false
+}
+
+// Every operator assignment that makes one equation hit its target.
+struct Assignments<'a> {
+    target: u64,
+    numbers: &'a [u64],
+    ops: &'a [&'a dyn Operator],
+    prune: bool,
+    // Ways to hit the target keyed by (index of the next number, partial
+    // result so far).
+    memo: FnvHashMap<(usize, u64), u64>,
+}
+
+impl<'a> Assignments<'a> {
+    fn new(target: u64, numbers: &'a [u64], ops: &'a [&'a dyn Operator]) -> Self {
+        Self {
+            target,
+            numbers,
+            ops,
+            prune: ops.iter().all(|op| op.is_monotonic()) && !numbers.contains(&0),
+            memo: FnvHashMap::default(),
+        }
+    }
+
+    fn count(&mut self) -> u64 {
+        self.count_from(1, self.numbers[0])
+    }
+
+    fn count_from(&mut self, idx: usize, result: u64) -> u64 {
+        if idx == self.numbers.len() {
+            return u64::from(result == self.target);
+        }
+        if self.prune && result > self.target {
+            return 0;
+        }
+        if let Some(&count) = self.memo.get(&(idx, result)) {
+            return count;
+        }
+
+        let ops = self.ops;
+        let count = ops
+            .iter()
+            .filter_map(|op| op.apply(result, self.numbers[idx]))
+            .map(|next| self.count_from(idx + 1, next))
+            .sum();
+        self.memo.insert((idx, result), count);
+        count
+    }
+
+    // Each assignment written out left to right, such as `81 * 40 + 27`.
+    fn expressions(&mut self) -> Vec<String> {
+        let mut expressions = vec![];
+        self.collect(1, self.numbers[0], &mut vec![], &mut expressions);
+        expressions
+    }
+
+    // Skips every branch the memoized count rules out.
+    fn collect(
+        &mut self,
+        idx: usize,
+        result: u64,
+        chosen: &mut Vec<&'a dyn Operator>,
+        expressions: &mut Vec<String>,
+    ) {
+        if self.count_from(idx, result) == 0 {
+            return;
+        }
+        if idx == self.numbers.len() {
+            let mut expression = self.numbers[0].to_string();
+            for (op, n) in chosen.iter().zip(&self.numbers[1..]) {
+                expression += &format!(" {} {n}", op.symbol());
+            }
+            expressions.push(expression);
+            return;
+        }
+
+        for &op in self.ops {
+            if let Some(next) = op.apply(result, self.numbers[idx]) {
+                chosen.push(op);
+                self.collect(idx + 1, next, chosen, expressions);
+                chosen.pop();
+            }
+        }
+    }
 }
 
 fn main() {
     println!("Part 1: {}", part_1(INPUT));
     println!("Part 2: {}", part_2(INPUT));
+
+    // The last `--ops=` also picks the operators for `--count` and
+    // `--expressions`, which are skipped if it is invalid.
+    let mut ops: Option<Vec<&dyn Operator>> = Some(vec![&Add, &Mul, &Concat]);
+    for arg in std::env::args().skip(1) {
+        if let Some(config) = arg.strip_prefix("--ops=") {
+            match parse_operators(config) {
+                Ok(parsed) => {
+                    println!("Operators {config}: {}", solve(INPUT, &parsed));
+                    ops = Some(parsed);
+                }
+                Err(err) => {
+                    eprintln!("{err}");
+                    ops = None;
+                }
+            }
+        }
+    }
+
+    let count = std::env::args().any(|arg| arg == "--count");
+    let enumerate = std::env::args().any(|arg| arg == "--expressions");
+    if let Some(ops) = ops.filter(|_| count || enumerate) {
+        for line in INPUT.lines() {
+            let (target, numbers) = parse_equation(line);
+            let mut assignments = Assignments::new(target, &numbers, &ops);
+            if count {
+                println!("{target}: {} assignments", assignments.count());
+            }
+            if enumerate {
+                for expression in assignments.expressions() {
+                    println!("{target} = {expression}");
+                }
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -119,6 +473,83 @@ mod tests {
         assert_eq!(part_2(EXAMPLE.as_bytes()), 11387);
     }
 
+    #[test]
+    fn test_operators() {
+        assert_eq!(Concat.apply(12, 345), Some(12345));
+        assert_eq!(Concat.apply(1, 123_456_789), Some(1_123_456_789));
+        assert_eq!(Concat.apply(7, 0), Some(70));
+        assert_eq!(Concat.apply(u64::MAX, 1), None);
+        assert_eq!(Sub.apply(3, 5), None);
+        assert_eq!(Div.apply(7, 2), Some(3));
+        assert_eq!(Div.apply(7, 0), None);
+        assert_eq!(Pow.apply(2, 10), Some(1024));
+
+        let ops = parse_operators("+,*,||").unwrap();
+        assert_eq!(solve(EXAMPLE.as_bytes(), &ops), 11387);
+        assert_eq!(
+            parse_operators("+,%").err(),
+            Some("unknown operator: %".to_owned())
+        );
+
+        // 5 - 3 and 12 / 4 only add up once partial results may shrink.
+        let ops = parse_operators("-,/").unwrap();
+        assert_eq!(solve(b"2: 5 3\n3: 12 4\n4: 20 5 1\n5: 20 5", &ops), 9);
+        let ops = parse_operators("**,^").unwrap();
+        assert_eq!(solve(b"1024: 2 10\n6: 5 3\n7: 2 3 1", &ops), 1030);
+    }
+
+    #[test]
+    fn test_backward_search() {
+        assert_eq!(Concat.unapply(12345, 345), Some(12));
+        assert_eq!(Concat.unapply(12345, 45), Some(123));
+        assert_eq!(Concat.unapply(12345, 44), None);
+        assert_eq!(Mul.unapply(12, 5), None);
+        assert_eq!(Pow.unapply(1024, 10), Some(2));
+        assert_eq!(Pow.unapply(1000, 2), None);
+        assert_eq!(Pow.unapply((1 << 60) + 1000, 1), Some((1 << 60) + 1000));
+        assert_eq!(Pow.unapply(3u64.pow(40), 40), Some(3));
+        assert_eq!(Pow.unapply(3u64.pow(40) - 1, 40), None);
+        assert_eq!(Pow.unapply(4_294_967_295u64.pow(2), 2), Some(4_294_967_295));
+        assert_eq!(Pow.unapply(u64::MAX, 2), None);
+        assert_eq!(Pow.unapply(0, 3), Some(0));
+        assert!(!Div.is_invertible());
+
+        let input = EXAMPLE.as_bytes();
+        let sets = ["+,*", "+,*,||", "+,-,*,||", "+,*,**,^", "+,*,/"];
+        for set in sets {
+            let ops = parse_operators(set).unwrap();
+            let forward = solve_with(input, &ops, Search::Forward);
+            if ops.iter().all(|op| op.is_invertible()) {
+                assert_eq!(solve_with(input, &ops, Search::Backward), forward, "{set}");
+            }
+            assert_eq!(solve(input, &ops), forward, "{set}");
+        }
+    }
+
+    #[test]
+    fn test_assignments() {
+        let ops: &[&dyn Operator] = &[&Add, &Mul, &Concat];
+        let counts = EXAMPLE
+            .lines()
+            .map(|line| {
+                let (target, numbers) = parse_equation(line.as_bytes());
+                Assignments::new(target, &numbers, ops).count()
+            })
+            .collect_vec();
+        assert_eq!(counts, [1, 2, 0, 1, 1, 0, 1, 0, 1]);
+
+        let mut assignments = Assignments::new(3267, &[81, 40, 27], ops);
+        assert_eq!(assignments.expressions(), ["81 + 40 * 27", "81 * 40 + 27"]);
+        let mut assignments = Assignments::new(7290, &[6, 8, 6, 15], ops);
+        assert_eq!(assignments.expressions(), ["6 * 8 || 6 * 15"]);
+
+        // Adding and multiplying two by two are interchangeable.
+        let ops: &[&dyn Operator] = &[&Add, &Mul];
+        let mut assignments = Assignments::new(4, &[2, 2, 1], ops);
+        assert_eq!(assignments.count(), 2);
+        assert_eq!(assignments.expressions(), ["2 + 2 * 1", "2 * 2 * 1"]);
+    }
+
     #[bench]
     fn bench_part_1(b: &mut test::Bencher) {
         b.iter(|| part_1(black_box(INPUT)));
@@ -128,4 +559,16 @@ mod tests {
     fn bench_part_2(b: &mut test::Bencher) {
         b.iter(|| part_2(black_box(INPUT)));
     }
+
+    #[bench]
+    fn bench_part_2_forward(b: &mut test::Bencher) {
+        let ops: &[&dyn Operator] = &[&Add, &Mul, &Concat];
+        b.iter(|| solve_with(black_box(INPUT), ops, Search::Forward));
+    }
+
+    #[bench]
+    fn bench_part_2_backward(b: &mut test::Bencher) {
+        let ops: &[&dyn Operator] = &[&Add, &Mul, &Concat];
+        b.iter(|| solve_with(black_box(INPUT), ops, Search::Backward));
+    }
 }
@@ -1,9 +1,11 @@
 #![feature(test)]
 extern crate test;
 
+use std::{iter, str::FromStr};
+
 use bstr::ByteSlice;
 use fnv::FnvHashMap;
-use itertools::Itertools;
+use itertools::{Either, Itertools};
 
 const INPUT: &[u8] = include_bytes!("../../inputs/day-08.txt");
 
@@ -28,87 +30,296 @@ fn parse_input(input: &[u8]) -> (Vec<&[u8]>, Antennas) {
     (map, antennas)
 }
 
-fn part_1(input: &[u8]) -> u64 {
-    let (map, antennas) = parse_input(input);
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum AntinodeRule {
+    // Points in line with two antennas where one antenna is `k` times as far
+    // away as the other, for every `k` in `ratios`. Points between the two
+    // antennas only count with `inner`.
+    Ratios { ratios: Vec<u32>, inner: bool },
+    // Every grid point in line with two antennas.
+    Line,
+}
+
+impl FromStr for AntinodeRule {
+    type Err = String;
+
+    // Either `line` or a comma-separated list of ratios, such as `2,3`, with
+    // an `inner:` prefix to include points between the antennas.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "line" {
+            return Ok(Self::Line);
+        }
+        let (ratios, inner) = match s.strip_prefix("inner:") {
+            Some(ratios) => (ratios, true),
+            None => (s, false),
+        };
+        ratios
+            .split(',')
+            .map(|k| k.parse().ok().filter(|&k| k > 0))
+            .collect::<Option<_>>()
+            .map(|ratios| Self::Ratios { ratios, inner })
+            .ok_or_else(|| format!("unknown antinode rule: {s}"))
+    }
+}
+
+fn gcd(a: i32, b: i32) -> i32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// With `a` and `b` two antennas, the points `a + t * (b - a)` for which one
+// antenna is `k` times as far away as the other, with `t` given as (p, q).
+// Those outside the antennas come first, then those between them.
+fn ratio_steps(k: i32, inner: bool) -> impl Iterator<Item = (i32, i32)> {
+    let outer = [(k, k - 1), (-1, k - 1)].into_iter().filter(move |_| k > 1);
+    outer.chain([(k, k + 1), (1, k + 1)].into_iter().filter(move |_| inner))
+}
+
+// Every antinode of one frequency as (y, x), once per pair of antennas that
+// produces it.
+fn candidates<'a>(
+    map: &[&[u8]],
+    antennas: &'a [(usize, usize)],
+    rule: &'a AntinodeRule,
+) -> impl Iterator<Item = (usize, usize)> + 'a {
+    let (height, width) = (map.len() as i32, map[0].len() as i32);
+    let on_map = move |&(y, x): &(i32, i32)| (0..height).contains(&y) && (0..width).contains(&x);
 
     antennas
         .iter()
-        .flat_map(|(_freq, antennas)| {
-            antennas.iter().tuple_combinations().flat_map(|(a, b)| {
-                let (a_y, a_x) = *a;
-                let (b_y, b_x) = *b;
-
-                let dx = a_x as i32 - b_x as i32;
-                let dy = a_y as i32 - b_y as i32;
-
-                let axp = usize::try_from(a_x as i32 + dx);
-                let ayp = usize::try_from(a_y as i32 + dy);
-
-                let bxp = usize::try_from(b_x as i32 - dx);
-                let byp = usize::try_from(b_y as i32 - dy);
-
-                [(ayp.ok(), axp.ok()), (byp.ok(), bxp.ok())]
-                    .into_iter()
-                    .filter_map(|(yo, xo)| {
-                        let (y, x) = (yo?, xo?);
-                        map.get(y)?.get(x).map(|_| (y, x))
-                    })
-            })
+        .tuple_combinations()
+        .flat_map(move |(&(a_y, a_x), &(b_y, b_x))| {
+            let (a_y, a_x) = (a_y as i32, a_x as i32);
+            let dy = b_y as i32 - a_y;
+            let dx = b_x as i32 - a_x;
+
+            match rule {
+                AntinodeRule::Ratios { ratios, inner } => Either::Left(
+                    ratios
+                        .iter()
+                        .flat_map(move |&k| ratio_steps(k as i32, *inner))
+                        .filter(move |&(p, q)| (dy * p) % q == 0 && (dx * p) % q == 0)
+                        .map(move |(p, q)| (a_y + dy * p / q, a_x + dx * p / q))
+                        .filter(on_map),
+                ),
+                AntinodeRule::Line => {
+                    // The smallest step between grid points on the line.
+                    let g = gcd(dy.abs(), dx.abs());
+                    let (dy, dx) = (dy / g, dx / g);
+
+                    Either::Right([1, -1].into_iter().flat_map(move |dir| {
+                        iter::successors(Some((a_y, a_x)), move |&(y, x)| {
+                            Some((y + dir * dy, x + dir * dx))
+                        })
+                        .take_while(on_map)
+                    }))
+                }
+            }
         })
-        .unique()
-        .count() as u64
+        .map(|(y, x)| (y as usize, x as usize))
 }
 
-fn test_locations(
-    map: &[&[u8]],
-    (mut y, mut x): (usize, usize),
-    (dy, dx): (i32, i32),
-) -> Vec<(usize, usize)> {
-    let mut locations = vec![(y, x)];
+// One bit per cell of the map.
+struct CellSet {
+    width: usize,
+    bits: Vec<u64>,
+}
 
-    loop {
-        let mut t = || {
-            y = usize::try_from(y as i32 + dy).ok()?;
-            x = usize::try_from(x as i32 + dx).ok()?;
+impl CellSet {
+    fn new(height: usize, width: usize) -> Self {
+        Self {
+            width,
+            bits: vec![0; (height * width).div_ceil(64)],
+        }
+    }
 
-            map.get(y)?.get(x).map(|_| (y, x))
-        };
+    #[inline]
+    fn insert(&mut self, (y, x): (usize, usize)) {
+        let i = y * self.width + x;
+        self.bits[i / 64] |= 1 << (i % 64);
+    }
 
-        let Some((y, x)) = t() else {
-            break;
-        };
+    #[inline]
+    fn contains(&self, (y, x): (usize, usize)) -> bool {
+        let i = y * self.width + x;
+        self.bits[i / 64] & (1 << (i % 64)) != 0
+    }
 
-        locations.push((y, x));
+    fn count(&self) -> usize {
+        self.bits
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
     }
 
-    locations
+    // How many cells are in both sets.
+    fn count_common(&self, other: &Self) -> usize {
+        self.bits
+            .iter()
+            .zip(&other.bits)
+            .map(|(a, b)| (a & b).count_ones() as usize)
+            .sum()
+    }
+
+    // The cells in the set as (y, x), in order.
+    fn positions(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.bits.iter().enumerate().flat_map(move |(i, &word)| {
+            (0..64)
+                .filter(move |bit| word & (1 << bit) != 0)
+                .map(move |bit| ((i * 64 + bit) / self.width, (i * 64 + bit) % self.width))
+        })
+    }
 }
 
-fn part_2(input: &[u8]) -> u64 {
-    let (map, antennas) = parse_input(input);
+fn frequency_set(map: &[&[u8]], antennas: &[(usize, usize)], rule: &AntinodeRule) -> CellSet {
+    let mut set = CellSet::new(map.len(), map[0].len());
+    for pos in candidates(map, antennas, rule) {
+        set.insert(pos);
+    }
+    set
+}
 
+// The sorted antinodes of every frequency as (y, x).
+fn antinodes(
+    map: &[&[u8]],
+    antennas: &Antennas,
+    rule: &AntinodeRule,
+) -> FnvHashMap<u8, Vec<(usize, usize)>> {
     antennas
         .iter()
-        .flat_map(|(_freq, antennas)| {
-            antennas.iter().tuple_combinations().flat_map(|(a, b)| {
-                let (a_y, a_x) = *a;
-                let (b_y, b_x) = *b;
+        .map(|(&freq, antennas)| {
+            let set = frequency_set(map, antennas, rule);
+            (freq, set.positions().collect())
+        })
+        .collect()
+}
 
-                let dx = a_x as i32 - b_x as i32;
-                let dy = a_y as i32 - b_y as i32;
+// The antinodes of every frequency sorted by frequency, then those of any
+// frequency and those shared by several frequencies.
+fn accumulate(
+    map: &[&[u8]],
+    antennas: &Antennas,
+    rule: &AntinodeRule,
+) -> (Vec<(u8, CellSet)>, CellSet, CellSet) {
+    let mut all = CellSet::new(map.len(), map[0].len());
+    let mut shared = CellSet::new(map.len(), map[0].len());
 
-                test_locations(&map, (a_y, a_x), (dy, dx))
-                    .into_iter()
-                    .chain(test_locations(&map, (b_y, b_x), (-dy, -dx)))
-            })
+    let sets = antennas
+        .iter()
+        .map(|(&freq, antennas)| (freq, frequency_set(map, antennas, rule)))
+        .sorted_by_key(|&(freq, _)| freq)
+        .collect_vec();
+    for (_, set) in &sets {
+        for ((all, shared), &word) in all.bits.iter_mut().zip(&mut shared.bits).zip(&set.bits) {
+            *shared |= *all & word;
+            *all |= word;
+        }
+    }
+
+    (sets, all, shared)
+}
+
+fn count_antinodes(input: &[u8], rule: &AntinodeRule) -> u64 {
+    let (map, antennas) = parse_input(input);
+    let mut all = CellSet::new(map.len(), map[0].len());
+    for antennas in antennas.values() {
+        for pos in candidates(&map, antennas, rule) {
+            all.insert(pos);
+        }
+    }
+
+    all.count() as u64
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FrequencyReport {
+    freq: u8,
+    antennas: usize,
+    antinodes: usize,
+    // Antinodes also produced by another frequency.
+    overlap: usize,
+}
+
+// One report per frequency, sorted by frequency.
+fn frequency_reports(
+    map: &[&[u8]],
+    antennas: &Antennas,
+    rule: &AntinodeRule,
+) -> Vec<FrequencyReport> {
+    let (sets, _, shared) = accumulate(map, antennas, rule);
+
+    sets.into_iter()
+        .map(|(freq, set)| FrequencyReport {
+            freq,
+            antennas: antennas[&freq].len(),
+            antinodes: set.count(),
+            overlap: set.count_common(&shared),
+        })
+        .collect()
+}
+
+// The map with a `#` on every antinode that isn't covered by an antenna.
+fn render_antinodes(map: &[&[u8]], antinodes: &CellSet) -> String {
+    map.iter()
+        .enumerate()
+        .map(|(y, row)| {
+            row.iter()
+                .enumerate()
+                .map(|(x, &c)| match c {
+                    b'.' if antinodes.contains((y, x)) => '#',
+                    c => c as char,
+                })
+                .collect::<String>()
         })
-        .unique()
-        .count() as u64
+        .join("\n")
+}
+
+fn part_1(input: &[u8]) -> u64 {
+    count_antinodes(input, &AntinodeRule::Ratios {
+        ratios: vec![2],
+        inner: false,
+    })
+}
+
+fn part_2(input: &[u8]) -> u64 {
+    count_antinodes(input, &AntinodeRule::Line)
 }
 
 fn main() {
     println!("Part 1: {}", part_1(INPUT));
     println!("Part 2: {}", part_2(INPUT));
+
+    if std::env::args().any(|arg| arg == "--report") {
+        let (map, antennas) = parse_input(INPUT);
+        let rule = AntinodeRule::Line;
+        for report in frequency_reports(&map, &antennas, &rule) {
+            println!(
+                "{}: {} antennas, {} antinodes, {} shared",
+                report.freq as char, report.antennas, report.antinodes, report.overlap
+            );
+        }
+        let (_, all, _) = accumulate(&map, &antennas, &rule);
+        println!("{}", render_antinodes(&map, &all));
+    }
+
+    for arg in std::env::args().skip(1) {
+        if let Some(rule) = arg.strip_prefix("--rule=") {
+            match rule.parse::<AntinodeRule>() {
+                Ok(rule) => {
+                    let (map, antennas) = parse_input(INPUT);
+                    for (freq, positions) in antinodes(&map, &antennas, &rule).into_iter().sorted()
+                    {
+                        println!("{}: {positions:?}", freq as char);
+                    }
+                    println!("{rule:?}: {}", count_antinodes(INPUT, &rule));
+                }
+                Err(err) => eprintln!("{err}"),
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -140,6 +351,118 @@ mod tests {
         assert_eq!(part_2(EXAMPLE.as_bytes()), 34);
     }
 
+    #[test]
+    fn test_antinode_rules() {
+        let input = "..........
+..........
+..........
+...a......
+..........
+..........
+a.........
+..........
+..........
+..........";
+        let (map, antennas) = parse_input(input.as_bytes());
+
+        // Part 1 only counts the points on either side of the antennas.
+        let ratios = antinodes(&map, &antennas, &"2".parse().unwrap());
+        assert_eq!(ratios[&b'a'], [(0, 6)]);
+        assert_eq!(part_1(input.as_bytes()), 1);
+
+        // One antenna twice as far away also holds between the two antennas.
+        let ratios = antinodes(&map, &antennas, &"inner:2".parse().unwrap());
+        assert_eq!(ratios[&b'a'], [(0, 6), (4, 2), (5, 1)]);
+        let ratios = antinodes(&map, &antennas, &"inner:2,3".parse().unwrap());
+        assert_eq!(ratios[&b'a'], [(0, 6), (4, 2), (5, 1)]);
+
+        // Antennas (2, 4) apart have a grid point halfway between them.
+        let (map, antennas) = parse_input(b"a.....\n......\n....a.\n......");
+        let line = antinodes(&map, &antennas, &AntinodeRule::Line);
+        assert_eq!(line[&b'a'], [(0, 0), (1, 2), (2, 4)]);
+        let ratios = antinodes(&map, &antennas, &"inner:1".parse().unwrap());
+        assert_eq!(ratios[&b'a'], [(1, 2)]);
+
+        assert_eq!("line".parse(), Ok(AntinodeRule::Line));
+        assert_eq!(
+            "2,3".parse(),
+            Ok(AntinodeRule::Ratios {
+                ratios: vec![2, 3],
+                inner: false
+            })
+        );
+        assert_eq!(
+            "inner:2".parse(),
+            Ok(AntinodeRule::Ratios {
+                ratios: vec![2],
+                inner: true
+            })
+        );
+        assert_eq!(
+            "0".parse::<AntinodeRule>(),
+            Err("unknown antinode rule: 0".to_owned())
+        );
+    }
+
+    // The `CellSet` accumulation, hashing every candidate instead.
+    fn count_antinodes_unique(input: &[u8], rule: &AntinodeRule) -> u64 {
+        let (map, antennas) = parse_input(input);
+
+        antennas
+            .values()
+            .flat_map(|antennas| candidates(&map, antennas, rule))
+            .unique()
+            .count() as u64
+    }
+
+    #[test]
+    fn test_frequency_reports() {
+        let (map, antennas) = parse_input(EXAMPLE.as_bytes());
+        let rule = AntinodeRule::Ratios {
+            ratios: vec![2],
+            inner: false,
+        };
+        assert_eq!(frequency_reports(&map, &antennas, &rule), [
+            FrequencyReport {
+                freq: b'0',
+                antennas: 4,
+                antinodes: 10,
+                overlap: 1
+            },
+            FrequencyReport {
+                freq: b'A',
+                antennas: 3,
+                antinodes: 5,
+                overlap: 1
+            }
+        ]);
+
+        let (_, all, _) = accumulate(&map, &antennas, &rule);
+        assert_eq!(
+            render_antinodes(&map, &all),
+            "......#....#
+...#....0...
+....#0....#.
+..#....0....
+....0....#..
+.#....A.....
+...#........
+#......#....
+........A...
+.........A..
+..........#.
+..........#."
+        );
+
+        for rule in ["2", "inner:2", "line"] {
+            let rule = rule.parse().unwrap();
+            assert_eq!(
+                count_antinodes(EXAMPLE.as_bytes(), &rule),
+                count_antinodes_unique(EXAMPLE.as_bytes(), &rule)
+            );
+        }
+    }
+
     #[bench]
     fn bench_part_1(b: &mut test::Bencher) {
         b.iter(|| part_1(black_box(INPUT)));
@@ -149,4 +472,15 @@ mod tests {
     fn bench_part_2(b: &mut test::Bencher) {
         b.iter(|| part_2(black_box(INPUT)));
     }
+
+    #[bench]
+    fn bench_part_1_unique(b: &mut test::Bencher) {
+        let rule = "2".parse().unwrap();
+        b.iter(|| count_antinodes_unique(black_box(INPUT), &rule));
+    }
+
+    #[bench]
+    fn bench_part_2_unique(b: &mut test::Bencher) {
+        b.iter(|| count_antinodes_unique(black_box(INPUT), &AntinodeRule::Line));
+    }
 }
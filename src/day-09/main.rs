@@ -1,9 +1,12 @@
 #![feature(test)]
 extern crate test;
 
-use std::collections::VecDeque;
-
-use itertools::Itertools;
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, VecDeque},
+    fmt,
+    ops::Range,
+};
 
 const INPUT: &[u8] = include_bytes!("../../inputs/day-09.txt");
 
@@ -33,7 +36,7 @@ fn part_1(input: &[u8]) -> u64 {
         })
         .collect::<VecDeque<_>>();
 
-    if input.len() % 2 != 0 {
+    if !input.len().is_multiple_of(2) {
         let size = parse_digit(*input.last().unwrap());
         let file_id = storage.len();
 
@@ -76,108 +79,337 @@ fn part_1(input: &[u8]) -> u64 {
     result
 }
 
-fn part_2(input: &[u8]) -> u64 {
-    let mut storage = input
-        .chunks_exact(2)
-        .enumerate()
-        .map(|(file_id, chunk)| {
-            let size = parse_digit(chunk[0]);
-            let empty = parse_digit(chunk[1]);
-            File {
-                file_id,
-                size,
-                empty,
-            }
-        })
-        .collect_vec();
+// Where the free spans of each size start, as min-heaps indexed by size.
+// Spans never grow past 9 blocks, as freed blocks are never reused.
+struct FreeSpans {
+    heaps: [BinaryHeap<Reverse<usize>>; 10],
+}
 
-    if input.len() % 2 != 0 {
-        let size = parse_digit(*input.last().unwrap());
-        let file_id = storage.len();
+impl FreeSpans {
+    fn new() -> Self {
+        Self {
+            heaps: Default::default(),
+        }
+    }
 
-        storage.push(File {
-            file_id,
-            size,
-            empty: 0,
-        });
+    fn insert(&mut self, pos: usize, size: usize) {
+        if size > 0 {
+            self.heaps[size].push(Reverse(pos));
+        }
     }
 
-    let mut file_order = vec![];
-    for i in 0..storage.len() {
-        file_order.push(i);
+    // Takes `size` blocks from the leftmost span that fits them, as long as it
+    // starts before `limit`. What is left of the span goes back in its bucket.
+    fn take(&mut self, size: usize, limit: usize) -> Option<usize> {
+        let (len, pos) = (size..self.heaps.len())
+            .filter_map(|len| Some((len, self.heaps[len].peek()?.0)))
+            .min_by_key(|&(_, pos)| pos)
+            .filter(|&(_, pos)| pos < limit)?;
+
+        self.heaps[len].pop();
+        self.insert(pos + size, len - size);
+        Some(pos)
     }
+}
 
-    for file_id in (1..storage.len()).rev() {
-        let file = storage[file_id];
+// `len` blocks of `file_id` moving from `from` to `to`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Move {
+    file_id: usize,
+    from: usize,
+    to: usize,
+    len: usize,
+}
 
-        let Some(target) = storage
-            .iter()
-            .filter(|f| {
-                f.file_id != file_id
-                    && f.empty >= file.size
-                    && file_order[f.file_id] < file_order[file_id]
-            })
-            .min_by_key(|f| file_order[f.file_id])
-        else {
-            continue;
-        };
-        let target_id: usize = target.file_id;
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "file {}: {} blocks {} -> {}",
+            self.file_id, self.len, self.from, self.to
+        )
+    }
+}
 
-        let prev_id = file_order
-            .iter()
-            .enumerate()
-            .find(|(_, &o)| o == file_order[file_id] - 1)
-            .unwrap()
-            .0;
-
-        storage[prev_id].empty += file.size + file.empty;
-        storage[file_id].empty = storage[target_id].empty - file.size;
-        storage[target_id].empty = 0;
-
-        // Update the file order
-        for i in 0..file_order.len() {
-            if file_order[i] > file_order[target_id] {
-                file_order[i] += 1;
+// The disk as laid out by the disk map, before any compaction.
+struct Disk {
+    // The (position, size) of every file, indexed by file id.
+    files: Vec<(usize, usize)>,
+    // The (position, size) of every free span.
+    free: Vec<(usize, usize)>,
+}
+
+impl Disk {
+    fn parse(input: &[u8]) -> Self {
+        let input = input.trim_ascii_end();
+        let mut files = Vec::with_capacity(input.len().div_ceil(2));
+        let mut free = Vec::with_capacity(input.len() / 2);
+        let mut pos = 0;
+        for (i, &c) in input.iter().enumerate() {
+            let size = parse_digit(c) as usize;
+            if i % 2 == 0 {
+                files.push((pos, size));
+            } else {
+                free.push((pos, size));
             }
+            pos += size;
         }
 
-        file_order[file_id] = file_order[target_id] + 1;
+        Self { files, free }
     }
 
-    storage.sort_unstable_by_key(|f| file_order[f.file_id]);
+    // The file id stored in every block, `None` for free blocks.
+    fn blocks(&self) -> Vec<Option<usize>> {
+        let end = self.files.iter().chain(&self.free);
+        let mut blocks = vec![None; end.map(|&(pos, size)| pos + size).max().unwrap_or(0)];
+        for (file_id, &(pos, size)) in self.files.iter().enumerate() {
+            blocks[pos..pos + size].fill(Some(file_id));
+        }
+        blocks
+    }
 
-    let mut idx = 0;
-    let mut result = 0;
-    for File {
-        file_id,
-        size,
-        empty,
-    } in storage
-    {
-        for _ in 0..size {
-            result += idx * file_id as u64;
-            idx += 1;
+    // Moves single blocks from the end of the disk into the leftmost free
+    // block, like `part_1`.
+    fn compact_blocks(&self) -> Vec<Move> {
+        let blocks = self.blocks();
+        let mut moves = vec![];
+        let (mut left, mut right) = (0, blocks.len());
+
+        loop {
+            while left < right && blocks[left].is_some() {
+                left += 1;
+            }
+            while left < right && blocks[right - 1].is_none() {
+                right -= 1;
+            }
+            if left + 1 >= right {
+                return moves;
+            }
+
+            right -= 1;
+            moves.push(Move {
+                file_id: blocks[right].unwrap(),
+                from: right,
+                to: left,
+                len: 1,
+            });
+            left += 1;
+        }
+    }
+
+    // Moves whole files by decreasing file id into the leftmost free span
+    // they fit in, like `part_2`.
+    fn compact_files(&self) -> impl Iterator<Item = Move> + '_ {
+        let mut free = FreeSpans::new();
+        for &(pos, size) in &self.free {
+            free.insert(pos, size);
         }
 
-        idx += empty;
+        self.files
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|&(_, &(_, size))| size > 0)
+            .filter_map(move |(file_id, &(from, len))| {
+                let to = free.take(len, from)?;
+                Some(Move {
+                    file_id,
+                    from,
+                    to,
+                    len,
+                })
+            })
+    }
+}
+
+fn apply_move(blocks: &mut [Option<usize>], m: &Move) {
+    for i in 0..m.len {
+        blocks[m.to + i] = blocks[m.from + i].take();
     }
+}
 
-    result
+// The blocks in `window` in the puzzle's notation, with `.` for free blocks
+// and file ids past 9 shown by their last digit.
+fn render_window(blocks: &[Option<usize>], window: Range<usize>) -> String {
+    let end = window.end.min(blocks.len());
+    blocks[window.start.min(end)..end]
+        .iter()
+        .map(|block| match block {
+            Some(file_id) => char::from(b'0' + (file_id % 10) as u8),
+            None => '.',
+        })
+        .collect()
+}
+
+fn render(blocks: &[Option<usize>]) -> String {
+    render_window(blocks, 0..blocks.len())
+}
+
+fn part_2(input: &[u8]) -> u64 {
+    let disk = Disk::parse(input);
+    let mut files = disk.files.clone();
+    for m in disk.compact_files() {
+        files[m.file_id].0 = m.to;
+    }
+
+    files
+        .into_iter()
+        .enumerate()
+        .map(|(file_id, (pos, size))| {
+            (file_id * (size * pos + size * size.saturating_sub(1) / 2)) as u64
+        })
+        .sum()
 }
 
 fn main() {
     println!("Part 1: {}", part_1(INPUT));
     println!("Part 2: {}", part_2(INPUT));
+
+    if std::env::args().any(|arg| arg == "--trace") {
+        let window = std::env::args().find_map(|arg| {
+            let (start, end) = arg.strip_prefix("--window=")?.split_once("..")?;
+            Some(start.parse().ok()?..end.parse().ok()?)
+        });
+
+        let show = |blocks: &[Option<usize>]| match &window {
+            Some(window) => render_window(blocks, window.clone()),
+            None => render(blocks),
+        };
+
+        let disk = Disk::parse(INPUT);
+        for (strategy, moves) in [
+            ("Blocks", disk.compact_blocks()),
+            ("Files", disk.compact_files().collect()),
+        ] {
+            let mut blocks = disk.blocks();
+            println!("{strategy}:\n{}", show(&blocks));
+            for m in moves {
+                apply_move(&mut blocks, &m);
+                println!("{} ({m})", show(&blocks));
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use std::hint::black_box;
 
+    use itertools::Itertools;
+
     use super::*;
 
     const EXAMPLE: &str = "2333133121414131402";
 
+    // Scans every file for a free span and renumbers the file order after every
+    // move, which is quadratic in the number of files.
+    fn part_2_quadratic(input: &[u8]) -> u64 {
+        let mut storage = input
+            .chunks_exact(2)
+            .enumerate()
+            .map(|(file_id, chunk)| {
+                let size = parse_digit(chunk[0]);
+                let empty = parse_digit(chunk[1]);
+                File {
+                    file_id,
+                    size,
+                    empty,
+                }
+            })
+            .collect_vec();
+
+        if !input.len().is_multiple_of(2) {
+            let size = parse_digit(*input.last().unwrap());
+            let file_id = storage.len();
+
+            storage.push(File {
+                file_id,
+                size,
+                empty: 0,
+            });
+        }
+
+        let mut file_order = vec![];
+        for i in 0..storage.len() {
+            file_order.push(i);
+        }
+
+        for file_id in (1..storage.len()).rev() {
+            let file = storage[file_id];
+
+            let Some(target) = storage
+                .iter()
+                .filter(|f| {
+                    f.file_id != file_id
+                        && f.empty >= file.size
+                        && file_order[f.file_id] < file_order[file_id]
+                })
+                .min_by_key(|f| file_order[f.file_id])
+            else {
+                continue;
+            };
+            let target_id: usize = target.file_id;
+
+            let prev_id = file_order
+                .iter()
+                .enumerate()
+                .find(|(_, &o)| o == file_order[file_id] - 1)
+                .unwrap()
+                .0;
+
+            storage[prev_id].empty += file.size + file.empty;
+            storage[file_id].empty = storage[target_id].empty - file.size;
+            storage[target_id].empty = 0;
+
+            // Update the file order
+            for i in 0..file_order.len() {
+                if file_order[i] > file_order[target_id] {
+                    file_order[i] += 1;
+                }
+            }
+
+            file_order[file_id] = file_order[target_id] + 1;
+        }
+
+        storage.sort_unstable_by_key(|f| file_order[f.file_id]);
+
+        let mut idx = 0;
+        let mut result = 0;
+        for File {
+            file_id,
+            size,
+            empty,
+        } in storage
+        {
+            for _ in 0..size {
+                result += idx * file_id as u64;
+                idx += 1;
+            }
+
+            idx += empty;
+        }
+
+        result
+    }
+
+    // A disk map of `len` random digits, with every file at least one block.
+    fn synthetic_disk_map(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        (0..len)
+            .map(|i| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                let digit = (state % 10) as u8;
+                if i % 2 == 0 {
+                    b'1' + digit % 9
+                } else {
+                    b'0' + digit
+                }
+            })
+            .collect()
+    }
+
     #[test]
     fn test_part_1() {
         assert_eq!(part_1(EXAMPLE.as_bytes()), 1928);
@@ -188,6 +420,93 @@ mod tests {
         assert_eq!(part_2(EXAMPLE.as_bytes()), 2858);
     }
 
+    #[test]
+    fn test_part_2_heaps() {
+        for len in [1, 2, 99, 1000, 2001] {
+            let input = synthetic_disk_map(len);
+            assert_eq!(part_2(&input), part_2_quadratic(&input), "{len} entries");
+        }
+    }
+
+    fn checksum(blocks: &[Option<usize>]) -> u64 {
+        blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(pos, block)| Some((pos * (*block)?) as u64))
+            .sum()
+    }
+
+    #[test]
+    fn test_disk() {
+        let disk = Disk::parse(b"12345");
+        let mut blocks = disk.blocks();
+        assert_eq!(render(&blocks), "0..111....22222");
+
+        let layouts = disk
+            .compact_blocks()
+            .iter()
+            .map(|m| {
+                apply_move(&mut blocks, m);
+                render(&blocks)
+            })
+            .collect_vec();
+        assert_eq!(layouts, [
+            "02.111....2222.",
+            "022111....222..",
+            "0221112...22...",
+            "02211122..2....",
+            "022111222......"
+        ]);
+
+        let disk = Disk::parse(EXAMPLE.as_bytes());
+        let mut blocks = disk.blocks();
+        assert_eq!(
+            render(&blocks),
+            "00...111...2...333.44.5555.6666.777.888899"
+        );
+        assert_eq!(render_window(&blocks, 38..50), "8899");
+        assert_eq!(render_window(&blocks, 60..70), "");
+
+        let moves = disk.compact_files().collect_vec();
+        assert_eq!(moves[0], Move {
+            file_id: 9,
+            from: 40,
+            to: 2,
+            len: 2
+        });
+        assert_eq!(moves[0].to_string(), "file 9: 2 blocks 40 -> 2");
+        for m in &moves {
+            apply_move(&mut blocks, m);
+        }
+        assert_eq!(
+            render(&blocks),
+            "00992111777.44.333....5555.6666.....8888.."
+        );
+        assert_eq!(checksum(&blocks), 2858);
+
+        let mut blocks = disk.blocks();
+        for m in &disk.compact_blocks() {
+            apply_move(&mut blocks, m);
+        }
+        assert_eq!(checksum(&blocks), 1928);
+
+        // Large maps render in windows, with ids past 9 by their last digit.
+        let input = synthetic_disk_map(1001);
+        let disk = Disk::parse(&input);
+        let mut blocks = disk.blocks();
+        assert_eq!(render_window(&blocks, 1000..1010).len(), 10);
+        for m in disk.compact_files() {
+            apply_move(&mut blocks, &m);
+        }
+        assert_eq!(checksum(&blocks), part_2(&input));
+
+        let mut blocks = disk.blocks();
+        for m in &disk.compact_blocks() {
+            apply_move(&mut blocks, m);
+        }
+        assert_eq!(checksum(&blocks), part_1(&input));
+    }
+
     #[bench]
     fn bench_part_1(b: &mut test::Bencher) {
         b.iter(|| part_1(black_box(INPUT)));
@@ -197,4 +516,10 @@ mod tests {
     fn bench_part_2(b: &mut test::Bencher) {
         b.iter(|| part_2(black_box(INPUT)));
     }
+
+    #[bench]
+    fn bench_part_2_synthetic(b: &mut test::Bencher) {
+        let input = synthetic_disk_map(1_000_000);
+        b.iter(|| part_2(black_box(&input)));
+    }
 }